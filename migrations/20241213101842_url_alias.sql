@@ -0,0 +1,11 @@
+-- Add migration script here
+ALTER TABLE urls
+    ALTER COLUMN short TYPE VARCHAR(64);
+
+-- the oldest url keeps a duplicated code, the others get their id appended, old codes have
+-- 6 characters so the new ones can not collide
+UPDATE urls SET short = short || '-' || id
+WHERE id NOT IN (SELECT MIN(id) FROM urls GROUP BY short);
+
+ALTER TABLE urls
+    ADD CONSTRAINT urls_short_key UNIQUE (short);
@@ -0,0 +1,6 @@
+-- Add migration script here
+-- urls are owned per user, the same url can be shortened by different users
+ALTER TABLE urls
+    DROP CONSTRAINT urls_url_key;
+
+CREATE INDEX urls_user_id_url_idx ON urls (user_id, url);
@@ -0,0 +1,6 @@
+-- Add migration script here
+ALTER TABLE urls
+    ADD COLUMN activate_at TIMESTAMPTZ,
+    ADD COLUMN expires_at TIMESTAMPTZ,
+    ADD COLUMN max_clicks BIGINT CHECK (max_clicks > 0),
+    ADD COLUMN clicks BIGINT NOT NULL DEFAULT 0;
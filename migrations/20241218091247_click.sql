@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE clicks (
+    id BIGSERIAL PRIMARY KEY,
+    url_id BIGINT NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    referrer TEXT,
+    user_agent TEXT,
+    -- sha256 of the salted client ip
+    ip_hash CHAR(64),
+    accept_language TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX clicks_url_id_created_at_idx ON clicks (url_id, created_at);
@@ -0,0 +1,7 @@
+-- Add migration script here
+ALTER TABLE urls
+    ADD COLUMN title TEXT;
+
+CREATE INDEX urls_user_id_created_at_idx ON urls (user_id, created_at, id);
+
+CREATE INDEX urls_user_id_clicks_idx ON urls (user_id, clicks, id);
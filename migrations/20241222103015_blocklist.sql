@@ -0,0 +1,17 @@
+-- Add migration script here
+ALTER TABLE users
+    ADD COLUMN role VARCHAR(16) NOT NULL DEFAULT 'user' CHECK (role IN ('user', 'admin'));
+
+CREATE TABLE blocklist (
+    id BIGSERIAL PRIMARY KEY,
+    -- domain: the host and its subdomains, regex: matched against the whole url
+    kind VARCHAR(16) NOT NULL CHECK (kind IN ('domain', 'regex')),
+    pattern TEXT NOT NULL,
+    reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (kind, pattern)
+);
+
+ALTER TABLE urls
+    ADD COLUMN disabled_at TIMESTAMPTZ,
+    ADD COLUMN disabled_reason TEXT;
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE urls
+    -- hashed argon2 password, the url asks for it before redirecting
+    ADD COLUMN password_hash VARCHAR(97);
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE urls
+    -- resolves left before the url is gone, NULL means unlimited
+    ADD COLUMN remaining_uses BIGINT CHECK (remaining_uses >= 0);
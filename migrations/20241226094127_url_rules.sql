@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE urls
+    -- ordered targeting rules, the first match overrides `url`
+    ADD COLUMN rules JSONB NOT NULL DEFAULT '[]';
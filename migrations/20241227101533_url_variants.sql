@@ -0,0 +1,9 @@
+-- Add migration script here
+ALTER TABLE urls
+    -- weighted destinations of a split test, one is picked for each visit
+    ADD COLUMN variants JSONB NOT NULL DEFAULT '[]',
+    -- visitors keep the variant they got first
+    ADD COLUMN sticky BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE clicks
+    ADD COLUMN variant VARCHAR(32);
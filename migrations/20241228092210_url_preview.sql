@@ -0,0 +1,8 @@
+-- Add migration script here
+ALTER TABLE urls
+    -- title, description, image and favicon of the destination page
+    ADD COLUMN preview JSONB,
+    -- set when the destination was fetched, even when the fetch failed
+    ADD COLUMN preview_fetched_at TIMESTAMPTZ;
+
+CREATE INDEX urls_url_preview_idx ON urls (url) WHERE preview IS NOT NULL;
@@ -0,0 +1,12 @@
+-- Add migration script here
+ALTER TABLE urls
+    -- status code of the last check, NULL when the destination could not be reached
+    ADD COLUMN health_status SMALLINT,
+    ADD COLUMN health_latency_ms INTEGER,
+    ADD COLUMN health_checked_at TIMESTAMPTZ,
+    -- failed checks in a row, the url is broken after `health.failures` of them
+    ADD COLUMN health_failures INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN broken_at TIMESTAMPTZ;
+
+CREATE INDEX urls_health_checked_at_idx ON urls (health_checked_at NULLS FIRST) WHERE disabled_at IS NULL;
+CREATE INDEX urls_broken_idx ON urls (user_id, broken_at) WHERE broken_at IS NOT NULL;
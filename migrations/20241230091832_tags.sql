@@ -0,0 +1,21 @@
+-- Add migration script here
+ALTER TABLE urls
+    ADD COLUMN note TEXT;
+
+-- tags and folders of a user, a url can be in any number of both
+CREATE TABLE tags (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind VARCHAR(16) NOT NULL CHECK (kind IN ('tag', 'folder')),
+    name VARCHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, kind, name)
+);
+
+CREATE TABLE url_tags (
+    url_id BIGINT NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    tag_id BIGINT NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    PRIMARY KEY (url_id, tag_id)
+);
+
+CREATE INDEX url_tags_tag_id_idx ON url_tags (tag_id);
@@ -0,0 +1,6 @@
+-- Add migration script here
+ALTER TABLE urls
+    -- query params set on the destination at redirect time, utm fields end up here
+    ADD COLUMN params JSONB NOT NULL DEFAULT '{}',
+    -- the query string of the short url is passed on to the destination
+    ADD COLUMN pass_query BOOLEAN NOT NULL DEFAULT FALSE;
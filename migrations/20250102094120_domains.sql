@@ -0,0 +1,29 @@
+-- Add migration script here
+CREATE TABLE domains (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- lowercase punycode host like `go.acme.com`
+    host VARCHAR(253) NOT NULL,
+    -- published in a TXT record to prove ownership
+    token CHAR(32) NOT NULL,
+    verified_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, host)
+);
+
+-- anyone may claim a host, only one of them can prove it
+CREATE UNIQUE INDEX domains_verified_host_key ON domains (host) WHERE verified_at IS NOT NULL;
+
+-- urls without a domain live on the default host, a domain with urls can not be deleted
+ALTER TABLE urls
+    ADD COLUMN domain_id BIGINT REFERENCES domains(id);
+
+-- short codes are unique per domain, and per user because the owner api addresses urls by them
+ALTER TABLE urls
+    DROP CONSTRAINT urls_short_key;
+
+CREATE UNIQUE INDEX urls_default_short_key ON urls (short) WHERE domain_id IS NULL;
+CREATE UNIQUE INDEX urls_domain_short_key ON urls (domain_id, short) WHERE domain_id IS NOT NULL;
+
+ALTER TABLE urls
+    ADD CONSTRAINT urls_user_short_key UNIQUE (user_id, short);
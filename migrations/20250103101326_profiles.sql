@@ -0,0 +1,24 @@
+-- Add migration script here
+-- public link-in-bio page of a user at `/@{username}`
+CREATE TABLE profiles (
+    user_id BIGINT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    -- lowercase, the page is found case insensitive
+    username VARCHAR(32) NOT NULL UNIQUE,
+    title VARCHAR(128),
+    bio TEXT,
+    views BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- the urls shown on the page, a deleted url leaves the page
+CREATE TABLE profile_links (
+    user_id BIGINT NOT NULL REFERENCES profiles(user_id) ON DELETE CASCADE,
+    url_id BIGINT NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    position INT NOT NULL,
+    title VARCHAR(128),
+    icon VARCHAR(2048),
+    PRIMARY KEY (user_id, url_id)
+);
+
+CREATE INDEX profile_links_url_id_idx ON profile_links (url_id);
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- app uris opened instead of the url on ios and android
+ALTER TABLE urls
+    ADD COLUMN app_links JSONB;
+
+-- served from `/.well-known/` on the custom domain so the apps can claim its links
+ALTER TABLE domains
+    ADD COLUMN apple_app_site_association JSONB,
+    ADD COLUMN asset_links JSONB;
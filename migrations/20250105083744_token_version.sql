@@ -0,0 +1,4 @@
+-- Add migration script here
+-- tokens carry the version they were signed with, bumping it revokes all of them
+ALTER TABLE users
+    ADD COLUMN token_version INT NOT NULL DEFAULT 0;
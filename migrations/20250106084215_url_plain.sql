@@ -0,0 +1,10 @@
+-- Add migration script here
+-- urls shortened without any option, only these are handed out again for the same destination
+ALTER TABLE urls
+    ADD COLUMN plain BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE urls SET plain = TRUE
+WHERE redirect_status = 302 AND activate_at IS NULL AND expires_at IS NULL AND max_clicks IS NULL
+    AND title IS NULL AND note IS NULL AND password_hash IS NULL AND remaining_uses IS NULL
+    AND rules = '[]' AND variants = '[]' AND params = '{}' AND NOT pass_query
+    AND app_links IS NULL;
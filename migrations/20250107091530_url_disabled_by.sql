@@ -0,0 +1,6 @@
+-- Add migration script here
+-- the block rule which disabled the url, deleting the rule enables its urls again
+ALTER TABLE urls
+    ADD COLUMN disabled_by BIGINT REFERENCES blocklist(id) ON DELETE SET NULL;
+
+CREATE INDEX urls_disabled_by_idx ON urls (disabled_by) WHERE disabled_by IS NOT NULL;
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- the owner api addresses urls on a custom domain with their host, so a user may take the same
+-- short code on every host
+ALTER TABLE urls
+    DROP CONSTRAINT urls_user_short_key;
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- set when an admin enables a disabled url, the rescan leaves it alone until its destinations change
+ALTER TABLE urls
+    ADD COLUMN screening_exempt BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,6 @@
+-- Add migration script here
+-- the length random short codes have grown to, shared by every instance and kept over restarts
+CREATE TABLE code_length (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    length INT NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Add migration script here
+-- logged out sessions, kept until the tokens carrying them would have expired anyway
+CREATE TABLE revoked_sessions (
+    sid VARCHAR(32) PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    revoked_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,148 @@
+use crate::AppError;
+use futures::future::BoxFuture;
+use reqwest::{header, Client};
+use serde::Deserialize;
+use std::{collections::HashMap, sync::RwLock, time::Duration};
+
+// TXT record lookups for the domain verification
+pub trait TxtResolver: Send + Sync {
+    fn txt<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Vec<String>, AppError>>;
+}
+
+// dns over https with the json api of cloudflare and google
+pub struct DohResolver {
+    client: Client,
+    url: String,
+}
+
+// records kept in memory, for tests and local setups
+#[derive(Default)]
+pub struct FakeResolver {
+    records: RwLock<HashMap<String, Vec<String>>>,
+}
+
+#[derive(Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Status")]
+    status: u32,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    kind: u16,
+    data: String,
+}
+
+const TXT: u16 = 16;
+// NXDOMAIN, the name has no records at all
+const NAME_ERROR: u32 = 3;
+
+impl DohResolver {
+    pub fn new(url: impl Into<String>) -> Result<Self, AppError> {
+        let client = Client::builder().timeout(Duration::from_secs(5)).build()?;
+
+        Ok(Self {
+            client,
+            url: url.into(),
+        })
+    }
+}
+
+impl TxtResolver for DohResolver {
+    fn txt<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Vec<String>, AppError>> {
+        Box::pin(async move {
+            let res: DohResponse = self
+                .client
+                .get(&self.url)
+                .query(&[("name", name), ("type", "TXT")])
+                .header(header::ACCEPT, "application/dns-json")
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            match res.status {
+                0 => Ok(res
+                    .answer
+                    .iter()
+                    .filter(|a| a.kind == TXT)
+                    .map(|a| join_txt(&a.data))
+                    .collect()),
+                NAME_ERROR => Ok(vec![]),
+                status => Err(AppError::UserError(format!(
+                    "{} by dns lookup failed with status {}",
+                    name, status
+                ))),
+            }
+        })
+    }
+}
+
+impl FakeResolver {
+    pub fn insert(&self, name: &str, value: &str) {
+        self.records
+            .write()
+            .expect("fake resolver lock poisoned")
+            .entry(name.to_ascii_lowercase())
+            .or_default()
+            .push(value.to_string());
+    }
+}
+
+impl TxtResolver for FakeResolver {
+    fn txt<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Vec<String>, AppError>> {
+        let records = self
+            .records
+            .read()
+            .expect("fake resolver lock poisoned")
+            .get(&name.to_ascii_lowercase())
+            .cloned()
+            .unwrap_or_default();
+
+        Box::pin(async move { Ok(records) })
+    }
+}
+
+// a long TXT value comes as several quoted strings like `"abc" "def"`
+fn join_txt(data: &str) -> String {
+    let parts: Vec<&str> = data
+        .split('"')
+        .enumerate()
+        .filter(|(i, _)| i % 2 == 1)
+        .map(|(_, s)| s)
+        .collect();
+
+    if parts.is_empty() {
+        data.to_string()
+    } else {
+        parts.concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn join_txt_should_work() {
+        assert_eq!(join_txt(r#""abc""#), "abc");
+        assert_eq!(join_txt(r#""abc" "def""#), "abcdef");
+        assert_eq!(join_txt("abc"), "abc");
+    }
+
+    #[tokio::test]
+    async fn fake_resolver_should_work() -> Result<()> {
+        let resolver = FakeResolver::default();
+        resolver.insert("_shortener.Go.Acme.com", "token");
+
+        assert_eq!(resolver.txt("_shortener.go.acme.com").await?, ["token"]);
+        assert!(resolver.txt("go.acme.com").await?.is_empty());
+
+        Ok(())
+    }
+}
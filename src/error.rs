@@ -1,4 +1,6 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use crate::validation::UrlError;
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use serde_json::json;
 use std::io;
 use thiserror::Error;
 
@@ -7,9 +9,33 @@ pub enum AppError {
     #[error("argon2 error password hash: {0}")]
     Argon2Error(#[from] argon2::password_hash::Error),
 
+    #[error("config error: {0}")]
+    ConfigError(String),
+
     #[error("user error: {0}")]
     UserError(String),
 
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("not found: {0}")]
+    NotFound(String),
+
+    #[error("conflict: {0}")]
+    Conflict(String),
+
+    #[error("gone: {0}")]
+    Gone(String),
+
+    #[error("too many requests: {0}")]
+    TooManyRequests(String),
+
+    #[error("invalid url: {0}")]
+    InvalidUrl(#[from] UrlError),
+
     #[error("io error: {0}")]
     IoError(#[from] io::Error),
 
@@ -19,20 +45,71 @@ pub enum AppError {
     #[error("serde yaml error: {0}")]
     SerdeYamlError(#[from] serde_yaml::Error),
 
+    #[error("serde json error: {0}")]
+    SerdeJsonError(#[from] serde_json::Error),
+
+    #[error("csv error: {0}")]
+    CsvError(#[from] csv::Error),
+
+    #[error("png error: {0}")]
+    PngError(#[from] png::EncodingError),
+
+    #[error("reqwest error: {0}")]
+    ReqwestError(#[from] reqwest::Error),
+
     #[error("sqlx error: {0}")]
     SqlxError(#[from] sqlx::Error),
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-        let status = match &self {
+        // validation errors tell the client which field is wrong and why
+        if let Self::InvalidUrl(e) = &self {
+            let body = json!({
+                "field": "url",
+                "code": e.code(),
+                "message": e.to_string(),
+            });
+            return (StatusCode::UNPROCESSABLE_ENTITY, Json(body)).into_response();
+        }
+
+        self.status().into_response()
+    }
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
             Self::Argon2Error(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::ConfigError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::UserError(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::Gone(_) => StatusCode::GONE,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::InvalidUrl(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::JwtError(_) => StatusCode::BAD_REQUEST,
             Self::SerdeYamlError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::SerdeJsonError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::CsvError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::PngError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::ReqwestError(_) => StatusCode::BAD_GATEWAY,
             Self::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        };
-        status.into_response()
+        }
+    }
+
+    // what `into_response` tells the client, the details of other errors stay in the logs
+    pub fn client_message(&self) -> String {
+        match self {
+            Self::InvalidUrl(e) => e.to_string(),
+            _ => self
+                .status()
+                .canonical_reason()
+                .unwrap_or_default()
+                .to_string(),
+        }
     }
 }
@@ -0,0 +1,226 @@
+use crate::{AppError, ShortenerConfig};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub const DEFAULT_ALPHABET: &str =
+    "_-0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+pub const DEFAULT_LENGTH: usize = 6;
+pub const MAX_LENGTH: usize = 64;
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    // random code of the current length
+    #[default]
+    Random,
+    // url id encoded with the alphabet
+    Sequential,
+}
+
+pub struct ShortGenerator {
+    strategy: Strategy,
+    alphabet: Vec<char>,
+    length: AtomicUsize,
+    max_retries: usize,
+}
+
+impl ShortGenerator {
+    pub fn new(config: &ShortenerConfig) -> Result<Self, AppError> {
+        let alphabet: Vec<char> = config.alphabet.chars().collect();
+        let unique: HashSet<_> = alphabet.iter().collect();
+
+        if alphabet.len() < 2 || alphabet.len() > u8::MAX as usize {
+            return Err(AppError::ConfigError(format!(
+                "{} by alphabet must have between 2 and 255 characters",
+                config.alphabet
+            )));
+        }
+
+        if unique.len() != alphabet.len() {
+            return Err(AppError::ConfigError(format!(
+                "{} by alphabet has duplicated characters",
+                config.alphabet
+            )));
+        }
+
+        // codes are a single path segment, other characters are not routed as they are written
+        if let Some(c) = alphabet
+            .iter()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '~')))
+        {
+            return Err(AppError::ConfigError(format!(
+                "{:?} by alphabet is not one of letters, digits, '_', '-' and '~'",
+                c
+            )));
+        }
+
+        if config.length == 0 || config.length > MAX_LENGTH {
+            return Err(AppError::ConfigError(format!(
+                "{} by length must be between 1 and {}",
+                config.length, MAX_LENGTH
+            )));
+        }
+
+        Ok(Self {
+            strategy: config.strategy,
+            alphabet,
+            length: AtomicUsize::new(config.length),
+            max_retries: config.max_retries,
+        })
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    pub fn max_retries(&self) -> usize {
+        self.max_retries
+    }
+
+    pub fn length(&self) -> usize {
+        self.length.load(Ordering::Relaxed)
+    }
+
+    pub fn random(&self) -> String {
+        let length = self.length();
+        nanoid::nanoid!(length, &self.alphabet)
+    }
+
+    pub fn encode(&self, id: i64) -> String {
+        let base = self.alphabet.len() as u64;
+        let mut n = id as u64;
+        let mut ret = Vec::new();
+
+        loop {
+            ret.push(self.alphabet[(n % base) as usize]);
+            n /= base;
+            if n == 0 {
+                break;
+            }
+        }
+
+        ret.iter().rev().collect()
+    }
+
+    // continue with a length grown before, it never shrinks
+    pub fn resume(&self, length: usize) -> usize {
+        let length = length.min(MAX_LENGTH);
+        self.length.fetch_max(length, Ordering::Relaxed).max(length)
+    }
+
+    // grow the code length by one when it is still `from`, so concurrent callers only grow once
+    pub fn grow(&self, from: usize) -> Result<usize, AppError> {
+        if from >= MAX_LENGTH {
+            return Err(AppError::Conflict(
+                "short code keyspace is exhausted".to_string(),
+            ));
+        }
+
+        let ret = self
+            .length
+            .compare_exchange(from, from + 1, Ordering::Relaxed, Ordering::Relaxed)
+            .map_or_else(|current| current, |_| from + 1);
+
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn random_should_work() -> Result<()> {
+        let config = ShortenerConfig {
+            alphabet: "abc".to_string(),
+            length: 8,
+            ..Default::default()
+        };
+        let generator = ShortGenerator::new(&config)?;
+
+        let short = generator.random();
+
+        assert_eq!(short.len(), 8);
+        assert!(short.chars().all(|c| "abc".contains(c)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn encode_should_work() -> Result<()> {
+        let config = ShortenerConfig {
+            alphabet: "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ".to_string(),
+            ..Default::default()
+        };
+        let generator = ShortGenerator::new(&config)?;
+
+        assert_eq!(generator.encode(0), "0");
+        assert_eq!(generator.encode(61), "Z");
+        assert_eq!(generator.encode(62), "10");
+        assert_eq!(generator.encode(3843), "ZZ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn grow_should_work() -> Result<()> {
+        let config = ShortenerConfig {
+            length: 6,
+            ..Default::default()
+        };
+        let generator = ShortGenerator::new(&config)?;
+
+        assert_eq!(generator.grow(6)?, 7);
+        // already grown by another caller
+        assert_eq!(generator.grow(6)?, 7);
+        assert_eq!(generator.length(), 7);
+
+        assert_eq!(generator.resume(9), 9);
+        assert_eq!(generator.resume(8), 9);
+        assert_eq!(generator.resume(100), MAX_LENGTH);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_config_should_fail() {
+        let config = ShortenerConfig {
+            alphabet: "a".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            ShortGenerator::new(&config),
+            Err(AppError::ConfigError(_))
+        ));
+
+        let config = ShortenerConfig {
+            alphabet: "abca".to_string(),
+            ..Default::default()
+        };
+        assert!(ShortGenerator::new(&config).is_err());
+
+        let config = ShortenerConfig {
+            length: 0,
+            ..Default::default()
+        };
+        assert!(ShortGenerator::new(&config).is_err());
+
+        for alphabet in [
+            "abc/", "abc?", "abc#", "abc%", "abc@", "abc.", "abc ", "abcé",
+        ] {
+            let config = ShortenerConfig {
+                alphabet: alphabet.to_string(),
+                ..Default::default()
+            };
+            assert!(
+                matches!(ShortGenerator::new(&config), Err(AppError::ConfigError(_))),
+                "{}",
+                alphabet
+            );
+        }
+    }
+}
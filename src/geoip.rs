@@ -0,0 +1,96 @@
+use crate::AppError;
+use ipnet::IpNet;
+use std::{fs, net::IpAddr};
+
+// country lookup from a csv of `network,country` rows like `1.0.0.0/24,AU`, the networks must
+// not overlap
+pub struct GeoIp {
+    ranges: Vec<(u128, u128, String)>,
+}
+
+impl GeoIp {
+    pub fn load(path: &str) -> Result<Self, AppError> {
+        let data = fs::read_to_string(path).map_err(|e| {
+            AppError::ConfigError(format!("{} by geoip file can not be read: {}", path, e))
+        })?;
+
+        Self::parse(&data)
+    }
+
+    pub fn parse(data: &str) -> Result<Self, AppError> {
+        let mut ranges = vec![];
+
+        for line in data.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("network") {
+                continue;
+            }
+
+            let (network, country) = line.split_once(',').ok_or_else(|| {
+                AppError::ConfigError(format!("{} by geoip row is invalid", line))
+            })?;
+            let network: IpNet = network.trim().parse().map_err(|_| {
+                AppError::ConfigError(format!("{} by geoip network is invalid", network))
+            })?;
+
+            ranges.push((
+                to_u128(network.network()),
+                to_u128(network.broadcast()),
+                country.trim().to_ascii_uppercase(),
+            ));
+        }
+
+        ranges.sort_by_key(|(start, _, _)| *start);
+
+        Ok(Self { ranges })
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<&str> {
+        let ip = to_u128(ip);
+        let i = self.ranges.partition_point(|(start, _, _)| *start <= ip);
+        let (_, end, country) = self.ranges.get(i.checked_sub(1)?)?;
+
+        (ip <= *end).then_some(country.as_str())
+    }
+}
+
+// ipv4 is mapped into ipv6 so both live in one sorted list
+fn to_u128(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn geoip_should_work() -> Result<()> {
+        let data = "network,country\n1.0.0.0/24,au\n8.8.8.0/24,US\n2001:da8::/32,CN\n";
+        let geoip = GeoIp::parse(data)?;
+
+        assert_eq!(geoip.lookup("1.0.0.1".parse()?), Some("AU"));
+        assert_eq!(geoip.lookup("8.8.8.8".parse()?), Some("US"));
+        assert_eq!(geoip.lookup("2001:da8::1".parse()?), Some("CN"));
+        assert_eq!(geoip.lookup("8.8.9.1".parse()?), None);
+        assert_eq!(geoip.lookup("0.0.0.1".parse()?), None);
+
+        assert!(matches!(
+            GeoIp::parse("bad,US"),
+            Err(AppError::ConfigError(_))
+        ));
+        assert!(matches!(
+            GeoIp::parse("1.0.0.0/24"),
+            Err(AppError::ConfigError(_))
+        ));
+        assert!(matches!(
+            GeoIp::load("no-such-geoip.csv"),
+            Err(AppError::ConfigError(_))
+        ));
+
+        Ok(())
+    }
+}
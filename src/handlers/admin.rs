@@ -0,0 +1,45 @@
+use crate::{AppError, AppState, CreateBlockRule, RescanOutput};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+pub async fn list_block_rules_handler(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let rules = state.list_block_rules().await?;
+    Ok((StatusCode::OK, Json(rules)))
+}
+
+pub async fn add_block_rule_handler(
+    State(state): State<AppState>,
+    Json(input): Json<CreateBlockRule>,
+) -> Result<impl IntoResponse, AppError> {
+    let rule = state.add_block_rule(input).await?;
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+pub async fn delete_block_rule_handler(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let rule = state.delete_block_rule(id).await?;
+    Ok((StatusCode::OK, Json(rule)))
+}
+
+pub async fn enable_url_handler(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let url = state.enable_url(id).await?;
+    Ok((StatusCode::OK, Json(url)))
+}
+
+pub async fn rescan_urls_handler(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let disabled = state.rescan_urls().await?;
+    Ok((StatusCode::OK, Json(RescanOutput { disabled })))
+}
@@ -0,0 +1,95 @@
+use super::urls::request_host;
+use crate::{AppError, AppState, CreateDomain, Domain, DomainApps, User};
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde_json::Value;
+
+pub async fn list_domains_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let domains = state.list_domains(user.id).await?;
+
+    Ok((StatusCode::OK, Json(domains)))
+}
+
+pub async fn create_domain_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateDomain>,
+) -> Result<impl IntoResponse, AppError> {
+    let domain = state.create_domain(user.id, input).await?;
+
+    Ok((StatusCode::CREATED, Json(domain)))
+}
+
+// look up the TXT record of the domain again
+pub async fn verify_domain_handler(
+    Path(id): Path<i64>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let domain = state.verify_domain(user.id, id).await?;
+
+    Ok((StatusCode::OK, Json(domain)))
+}
+
+pub async fn delete_domain_handler(
+    Path(id): Path<i64>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let domain = state.delete_domain(user.id, id).await?;
+
+    Ok((StatusCode::OK, Json(domain)))
+}
+
+pub async fn set_domain_apps_handler(
+    Path(id): Path<i64>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<DomainApps>,
+) -> Result<impl IntoResponse, AppError> {
+    let domain = state.set_domain_apps(user.id, id, input).await?;
+
+    Ok((StatusCode::OK, Json(domain)))
+}
+
+// `/.well-known/apple-app-site-association` of the custom domain in the `Host`
+pub async fn apple_app_site_association_handler(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let file = app_file(&state, &headers, |d| d.apple_app_site_association).await?;
+
+    Ok((StatusCode::OK, Json(file)))
+}
+
+// `/.well-known/assetlinks.json` of the custom domain in the `Host`
+pub async fn asset_links_handler(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let file = app_file(&state, &headers, |d| d.asset_links).await?;
+
+    Ok((StatusCode::OK, Json(file)))
+}
+
+async fn app_file(
+    state: &AppState,
+    headers: &HeaderMap,
+    file: impl FnOnce(Domain) -> Option<sqlx::types::Json<Value>>,
+) -> Result<Value, AppError> {
+    let host = request_host(headers).unwrap_or_default();
+
+    state
+        .find_domain_by_host(&host)
+        .await?
+        .and_then(file)
+        .map(|file| file.0)
+        .ok_or_else(|| AppError::NotFound(format!("{} by app file not find", host)))
+}
@@ -1,17 +1,32 @@
+mod admin;
+mod domains;
+mod profiles;
+mod tags;
 mod urls;
 mod users;
 
+pub use admin::*;
+pub use domains::*;
+pub use profiles::*;
+pub use tags::*;
 pub use urls::*;
 pub use users::*;
 
 #[cfg(test)]
 mod tests {
-    use crate::{get_router, AppState, MoreOutput, Output};
+    use crate::{
+        get_router, AppState, BulkOutput, Domain, Output, ProfilePage, RuleMatch, Tag, Url,
+        UrlList, UrlStats,
+    };
     use anyhow::Result;
-    use reqwest::Client;
+    use reqwest::{
+        multipart::{Form, Part},
+        redirect::Policy,
+        Client,
+    };
     use serde::Deserialize;
-    use std::net::SocketAddr;
-    use tokio::net::TcpListener;
+    use std::{net::SocketAddr, time::Duration};
+    use tokio::{net::TcpListener, time::sleep};
 
     const ADDR: &str = "0.0.0.0:0";
 
@@ -33,7 +48,22 @@ mod tests {
         let mut server = Server::new(state).await?;
         server.path = server.short().await?;
         server.get_url().await?;
+        server.redirect().await?;
+        server.stats().await?;
+        server.qr().await?;
         server.get_urls().await?;
+        server.tags().await?;
+        server.domains().await?;
+        server.profile().await?;
+        server.export().await?;
+        server.update_url().await?;
+        server.delete_url().await?;
+        server.bulk().await?;
+        server.protected().await?;
+        server.rules().await?;
+        server.variants().await?;
+        server.params().await?;
+        server.apps().await?;
         server.token = server.change().await?;
         server.delete().await?;
 
@@ -47,12 +77,15 @@ mod tests {
             let addr = listener.local_addr()?;
 
             tokio::spawn(async move {
-                axum::serve(listener, app.into_make_service())
-                    .await
-                    .unwrap()
+                axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .await
+                .unwrap()
             });
 
-            let client = Client::new();
+            let client = Client::builder().redirect(Policy::none()).build()?;
 
             let mut ret = Self {
                 addr,
@@ -111,6 +144,17 @@ mod tests {
             assert_eq!(res.status(), 200);
 
             let ret: AuthToken = res.json().await?;
+
+            // the new password revoked the old token
+            let res = self
+                .client
+                .get(format!("http://{}/urls", self.addr))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 401);
+
             Ok(ret.token)
         }
 
@@ -133,7 +177,7 @@ mod tests {
                 .post(format!("http://{}/short", self.addr))
                 .header("Authorization", format!("Bearer {}", self.token))
                 .header("Content-Type", "application/json")
-                .body(r#"{"url": "www.360.com"}"#)
+                .body(r#"{"url": "https://www.360.com/"}"#)
                 .send()
                 .await?;
 
@@ -141,14 +185,276 @@ mod tests {
 
             let ret: Output = res.json().await?;
 
+            let res = self
+                .client
+                .post(format!("http://{}/short", self.addr))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .header("Content-Type", "application/json")
+                .body(r#"{"url": "javascript:alert(1)"}"#)
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 422);
+
+            let err: serde_json::Value = res.json().await?;
+
+            assert_eq!(err["field"], "url");
+            assert_eq!(err["code"], "scheme_not_allowed");
+
             Ok(ret.output)
         }
 
         async fn get_url(&self) -> Result<()> {
+            let res = self
+                .client
+                .get(format!("http://{}/urls/{}", self.addr, self.path))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let ret: Url = res.json().await?;
+
+            assert_eq!(ret.short, self.path);
+            assert_eq!(ret.url, "https://www.360.com/");
+
+            Ok(())
+        }
+
+        async fn update_url(&self) -> Result<()> {
+            let res = self
+                .client
+                .patch(format!("http://{}/urls/{}", self.addr, self.path))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .header("Content-Type", "application/json")
+                .body(r#"{"url": "https://www.qq.com/", "redirect_status": 301}"#)
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let ret: Url = res.json().await?;
+
+            assert_eq!(ret.url, "https://www.qq.com/");
+            assert_eq!(ret.redirect_status, 301);
+
+            let res = self
+                .client
+                .get(format!("http://{}/{}", self.addr, self.path))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 301);
+            assert_eq!(res.headers()["Location"], "https://www.qq.com/");
+
+            Ok(())
+        }
+
+        async fn delete_url(&self) -> Result<()> {
+            let res = self
+                .client
+                .delete(format!("http://{}/urls/{}", self.addr, self.path))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let res = self
+                .client
+                .get(format!("http://{}/urls/{}", self.addr, self.path))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 404);
+
+            Ok(())
+        }
+
+        async fn redirect(&self) -> Result<()> {
             let res = self
                 .client
                 .get(format!("http://{}/{}", self.addr, self.path))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 302);
+            assert_eq!(res.headers()["Location"], "https://www.360.com/");
+
+            let res = self
+                .client
+                .get(format!("http://{}/nothing", self.addr))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 404);
+
+            Ok(())
+        }
+
+        async fn stats(&self) -> Result<()> {
+            // clicks are recorded in the background
+            for _ in 0..20 {
+                let res = self
+                    .client
+                    .get(format!(
+                        "http://{}/urls/{}/stats?bucket=hour",
+                        self.addr, self.path
+                    ))
+                    .header("Authorization", format!("Bearer {}", self.token))
+                    .send()
+                    .await?;
+
+                assert_eq!(res.status(), 200);
+
+                let ret: UrlStats = res.json().await?;
+
+                if ret.total == 1 {
+                    assert_eq!(ret.unique_visitors, 1);
+                    assert_eq!(ret.series.len(), 1);
+                    return Ok(());
+                }
+
+                sleep(Duration::from_millis(50)).await;
+            }
+
+            panic!("click should be recorded");
+        }
+
+        async fn qr(&self) -> Result<()> {
+            let res = self
+                .client
+                .get(format!("http://{}/urls/{}/qr", self.addr, self.path))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+            assert_eq!(res.headers()["Content-Type"], "image/png");
+            assert!(res.bytes().await?.starts_with(b"\x89PNG"));
+
+            let res = self
+                .client
+                .get(format!(
+                    "http://{}/urls/{}/qr?format=svg&level=h&fg=%23336699",
+                    self.addr, self.path
+                ))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+            assert_eq!(res.headers()["Content-Type"], "image/svg+xml");
+            assert!(res.text().await?.contains("#336699"));
+
+            let res = self
+                .client
+                .get(format!("http://{}/urls/{}/qr?size=1", self.addr, self.path))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 400);
+
+            Ok(())
+        }
+
+        async fn rules(&self) -> Result<()> {
+            let res = self
+                .client
+                .post(format!("http://{}/short", self.addr))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .header("Content-Type", "application/json")
+                .body(
+                    r#"{"url": "https://www.qq.com/", "rules": [{"language": ["zh"], "url": "https://www.qq.com/zh"}]}"#,
+                )
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let ret: Output = res.json().await?;
+
+            let res = self
+                .client
+                .get(format!("http://{}/{}", self.addr, ret.output))
+                .header("Accept-Language", "zh-CN,zh;q=0.9,en;q=0.8")
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 302);
+            assert_eq!(res.headers()["Location"], "https://www.qq.com/zh");
+
+            let res = self
+                .client
+                .post(format!(
+                    "http://{}/urls/{}/rules/test",
+                    self.addr, ret.output
+                ))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .header("Content-Type", "application/json")
+                .body(r#"{"accept_language": "en-US"}"#)
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let ret: RuleMatch = res.json().await?;
+
+            assert_eq!(ret.rule, None);
+            assert_eq!(ret.url, "https://www.qq.com/");
+
+            Ok(())
+        }
+
+        async fn variants(&self) -> Result<()> {
+            let res = self
+                .client
+                .post(format!("http://{}/short", self.addr))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .header("Content-Type", "application/json")
+                .body(
+                    r#"{"url": "https://www.qq.com/", "sticky": true, "variants": [{"name": "a", "url": "https://www.qq.com/a", "weight": 1}, {"name": "b", "url": "https://www.qq.com/b", "weight": 1}]}"#,
+                )
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let ret: Output = res.json().await?;
+            let path = format!("http://{}/{}", self.addr, ret.output);
+
+            let res = self.client.get(&path).send().await?;
+
+            assert_eq!(res.status(), 302);
+            let cookie = res.headers()["Set-Cookie"].to_str()?.to_string();
+            assert!(cookie.starts_with(&format!("v_{}=", ret.output)));
+
+            // the cookie picks the variant, whatever the weights say
+            let res = self
+                .client
+                .get(&path)
+                .header("Cookie", format!("other=1; v_{}=b", ret.output))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 302);
+            assert_eq!(res.headers()["Location"], "https://www.qq.com/b");
+
+            Ok(())
+        }
+
+        async fn params(&self) -> Result<()> {
+            let res = self
+                .client
+                .post(format!("http://{}/short", self.addr))
                 .header("Authorization", format!("Bearer {}", self.token))
+                .header("Content-Type", "application/json")
+                .body(
+                    r#"{"url": "https://www.qq.com/news", "utm": {"source": "flyer"}, "pass_query": true}"#,
+                )
                 .send()
                 .await?;
 
@@ -156,7 +462,331 @@ mod tests {
 
             let ret: Output = res.json().await?;
 
-            assert_eq!(ret.output, "www.360.com");
+            let res = self
+                .client
+                .get(format!("http://{}/{}?id=7", self.addr, ret.output))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 302);
+            assert_eq!(
+                res.headers()["Location"],
+                "https://www.qq.com/news?id=7&utm_source=flyer"
+            );
+
+            Ok(())
+        }
+
+        async fn protected(&self) -> Result<()> {
+            let res = self
+                .client
+                .post(format!("http://{}/short", self.addr))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .header("Content-Type", "application/json")
+                .body(r#"{"url": "https://www.qq.com/docs", "password": "secret"}"#)
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let ret: Output = res.json().await?;
+            let path = format!("http://{}/{}", self.addr, ret.output);
+
+            let res = self.client.get(&path).send().await?;
+
+            assert_eq!(res.status(), 401);
+            assert!(res.text().await?.contains("<form"));
+
+            let res = self
+                .client
+                .post(&path)
+                .form(&[("password", "wrong")])
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 401);
+
+            let res = self
+                .client
+                .post(&path)
+                .form(&[("password", "secret")])
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 302);
+            assert_eq!(res.headers()["Location"], "https://www.qq.com/docs");
+
+            Ok(())
+        }
+
+        async fn bulk(&self) -> Result<()> {
+            let res = self
+                .client
+                .post(format!("http://{}/short/bulk", self.addr))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .header("Content-Type", "application/x-ndjson")
+                .body("{\"url\": \"https://www.baidu.com/\"}\n{\"url\": \"https://www.qq.com/\", \"alias\": \"login\"}\n")
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let ret: BulkOutput = res.json().await?;
+
+            assert_eq!(ret.items.len(), 2);
+            assert!(ret.items[0].short.is_some());
+            assert!(ret.items[1].error.is_some());
+
+            let form = Form::new().part(
+                "file",
+                Part::bytes(
+                    b"url,alias\nhttps://www.baidu.com/,\nhttps://www.360.com/,three-six\n"
+                        .to_vec(),
+                )
+                .file_name("urls.csv"),
+            );
+            let res = self
+                .client
+                .post(format!("http://{}/short/bulk", self.addr))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .multipart(form)
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let ret: BulkOutput = res.json().await?;
+
+            assert_eq!(ret.items.len(), 2);
+            assert_eq!(ret.items[1].short.as_deref(), Some("three-six"));
+
+            Ok(())
+        }
+
+        async fn export(&self) -> Result<()> {
+            let res = self
+                .client
+                .get(format!(
+                    "http://{}/urls/export?format=csv&clicks=true",
+                    self.addr
+                ))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+            assert_eq!(res.headers()["Content-Type"], "text/csv");
+
+            let data = res.text().await?;
+            let lines: Vec<&str> = data.lines().collect();
+
+            assert_eq!(lines.len(), 2);
+            assert!(lines[0].ends_with(",clicks"));
+            assert!(lines[1].contains(&self.path));
+
+            Ok(())
+        }
+
+        async fn tags(&self) -> Result<()> {
+            let res = self
+                .client
+                .post(format!("http://{}/tags", self.addr))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .header("Content-Type", "application/json")
+                .body(r#"{"kind": "folder", "name": "flyers"}"#)
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 201);
+
+            let folder: Tag = res.json().await?;
+
+            let res = self
+                .client
+                .put(format!("http://{}/urls/{}/tags", self.addr, self.path))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .header("Content-Type", "application/json")
+                .body(format!(r#"{{"tags": [{}]}}"#, folder.id))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let res = self
+                .client
+                .get(format!("http://{}/urls?folder={}", self.addr, folder.id))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let ret: UrlList = res.json().await?;
+
+            assert_eq!(ret.items.len(), 1);
+            assert_eq!(ret.items[0].short, self.path);
+
+            let res = self
+                .client
+                .delete(format!("http://{}/tags/{}", self.addr, folder.id))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            Ok(())
+        }
+
+        async fn apps(&self) -> Result<()> {
+            let res = self
+                .client
+                .post(format!("http://{}/short", self.addr))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .header("Content-Type", "application/json")
+                .body(r#"{"url": "https://www.qq.com/item/1", "app_links": {"ios": "acme://item/1?a=</script>"}}"#)
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let ret: Output = res.json().await?;
+            let path = format!("http://{}/{}", self.addr, ret.output);
+
+            let res = self
+                .client
+                .get(&path)
+                .header(
+                    "User-Agent",
+                    "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X)",
+                )
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let body = res.text().await?;
+            assert!(body.contains(r#"window.location.href = "acme://item/1?a=\u003c/script>";"#));
+            assert!(body.contains(r#"href="https://www.qq.com/item/1""#));
+
+            // other platforms go straight to the web url
+            let res = self
+                .client
+                .get(&path)
+                .header("User-Agent", "Mozilla/5.0 (Linux; Android 14; Pixel 8)")
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 302);
+            assert_eq!(res.headers()["Location"], "https://www.qq.com/item/1");
+
+            Ok(())
+        }
+
+        async fn profile(&self) -> Result<()> {
+            let res = self
+                .client
+                .put(format!("http://{}/profile", self.addr))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .header("Content-Type", "application/json")
+                .body(format!(
+                    r#"{{"username": "TeamMeng", "bio": "<b>hi</b>", "links": [{{"short": "{}", "title": "Home"}}]}}"#,
+                    self.path
+                ))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let res = self
+                .client
+                .get(format!("http://{}/@teammeng", self.addr))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let body = res.text().await?;
+            assert!(body.contains(">Home</a>"));
+            assert!(body.contains("&lt;b&gt;hi&lt;/b&gt;"));
+
+            let res = self
+                .client
+                .get(format!("http://{}/@TeamMeng", self.addr))
+                .header("Accept", "application/json")
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let page: ProfilePage = res.json().await?;
+            assert_eq!(page.profile.views, 2);
+            assert_eq!(page.links[0].short, self.path);
+
+            let res = self
+                .client
+                .get(format!("http://{}/@nobody", self.addr))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 404);
+
+            Ok(())
+        }
+
+        async fn domains(&self) -> Result<()> {
+            let res = self
+                .client
+                .post(format!("http://{}/domains", self.addr))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .header("Content-Type", "application/json")
+                .body(r#"{"host": "go.acme.com"}"#)
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 201);
+
+            let domain: Domain = res.json().await?;
+
+            // the TXT record is not published
+            let res = self
+                .client
+                .post(format!("http://{}/domains/{}/verify", self.addr, domain.id))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 400);
+
+            let res = self
+                .client
+                .post(format!("http://{}/short", self.addr))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .header("Content-Type", "application/json")
+                .body(r#"{"url": "https://www.acme.com/", "domain": "go.acme.com"}"#)
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 403);
+
+            // unknown hosts use the default namespace
+            let res = self
+                .client
+                .get(format!("http://{}/{}", self.addr, self.path))
+                .header("Host", "go.acme.com")
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 302);
+
+            let res = self
+                .client
+                .delete(format!("http://{}/domains/{}", self.addr, domain.id))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
 
             Ok(())
         }
@@ -171,9 +801,25 @@ mod tests {
 
             assert_eq!(res.status(), 200);
 
-            let ret: MoreOutput = res.json().await?;
+            let ret: UrlList = res.json().await?;
+
+            assert_eq!(ret.items.len(), 1);
+            assert_eq!(ret.items[0].short, self.path);
+            assert!(ret.next_cursor.is_none());
+
+            // nothing was checked yet, `broken` is not taken for a short code
+            let res = self
+                .client
+                .get(format!("http://{}/urls/broken", self.addr))
+                .header("Authorization", format!("Bearer {}", self.token))
+                .send()
+                .await?;
+
+            assert_eq!(res.status(), 200);
+
+            let ret: Vec<Url> = res.json().await?;
 
-            assert_eq!(ret.output.len(), 1);
+            assert!(ret.is_empty());
 
             Ok(())
         }
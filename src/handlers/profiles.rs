@@ -0,0 +1,113 @@
+use super::urls::escape_html;
+use crate::{AppError, AppState, ProfilePage, UpdateProfile, User};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Extension, Json,
+};
+
+pub async fn get_profile_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let page = state.get_profile(user.id).await?;
+
+    Ok((StatusCode::OK, Json(page)))
+}
+
+pub async fn update_profile_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateProfile>,
+) -> Result<impl IntoResponse, AppError> {
+    let page = state.update_profile(user.id, input).await?;
+
+    Ok((StatusCode::OK, Json(page)))
+}
+
+pub async fn delete_profile_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = state.delete_profile(user.id).await?;
+
+    Ok((StatusCode::OK, Json(profile)))
+}
+
+// the public page at `/@{username}`, json when the client asks for it
+pub async fn profile_page_handler(
+    Path(username): Path<String>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let page = state.visit_profile(&username).await?;
+
+    let json = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("application/json"));
+
+    if json {
+        Ok((StatusCode::OK, Json(page)).into_response())
+    } else {
+        Ok((StatusCode::OK, Html(render_profile(&page))).into_response())
+    }
+}
+
+fn render_profile(page: &ProfilePage) -> String {
+    let profile = &page.profile;
+    let title = escape_html(
+        profile
+            .title
+            .as_deref()
+            .unwrap_or(&format!("@{}", profile.username)),
+    );
+    let bio = profile
+        .bio
+        .as_deref()
+        .map(|bio| format!("<p>{}</p>\n", escape_html(bio)))
+        .unwrap_or_default();
+
+    let links: String = page
+        .links
+        .iter()
+        .map(|link| {
+            let icon = link
+                .icon
+                .as_deref()
+                .map(|icon| {
+                    format!(
+                        r#"<img src="{}" alt="" width="24" height="24"> "#,
+                        escape_html(icon)
+                    )
+                })
+                .unwrap_or_default();
+            format!(
+                "<li><a href=\"{}\" rel=\"noopener\">{}{}</a></li>\n",
+                escape_html(&link.href),
+                icon,
+                escape_html(&link.title)
+            )
+        })
+        .collect();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+</head>
+<body>
+<main>
+<h1>{title}</h1>
+{bio}<ul>
+{links}</ul>
+</main>
+</body>
+</html>
+"#
+    )
+}
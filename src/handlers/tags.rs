@@ -0,0 +1,77 @@
+use crate::{
+    AppError, AppState, AssignTags, CreateTag, DomainQuery, ListTags, RenameTag, UrlKey, User,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+
+pub async fn list_tags_handler(
+    Query(query): Query<ListTags>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let tags = state.list_tags(user.id, query).await?;
+
+    Ok((StatusCode::OK, Json(tags)))
+}
+
+pub async fn create_tag_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateTag>,
+) -> Result<impl IntoResponse, AppError> {
+    let tag = state.create_tag(user.id, input).await?;
+
+    Ok((StatusCode::CREATED, Json(tag)))
+}
+
+pub async fn rename_tag_handler(
+    Path(id): Path<i64>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<RenameTag>,
+) -> Result<impl IntoResponse, AppError> {
+    let tag = state.rename_tag(user.id, id, input).await?;
+
+    Ok((StatusCode::OK, Json(tag)))
+}
+
+pub async fn delete_tag_handler(
+    Path(id): Path<i64>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let tag = state.delete_tag(user.id, id).await?;
+
+    Ok((StatusCode::OK, Json(tag)))
+}
+
+pub async fn get_url_tags_handler(
+    Path(id): Path<String>,
+    Query(on): Query<DomainQuery>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let tags = state
+        .get_url_tags(user.id, UrlKey::new(&id, on.domain.as_deref()))
+        .await?;
+
+    Ok((StatusCode::OK, Json(tags)))
+}
+
+pub async fn set_url_tags_handler(
+    Path(id): Path<String>,
+    Query(on): Query<DomainQuery>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<AssignTags>,
+) -> Result<impl IntoResponse, AppError> {
+    let tags = state
+        .set_url_tags(user.id, UrlKey::new(&id, on.domain.as_deref()), input)
+        .await?;
+
+    Ok((StatusCode::OK, Json(tags)))
+}
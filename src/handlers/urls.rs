@@ -1,10 +1,21 @@
-use crate::{AppError, AppState, CreateUrl, User};
+use crate::{
+    models::{hash_ip, parse_csv, parse_json, parse_ndjson},
+    qr, AppError, AppState, Click, CreateUrl, DomainQuery, ExportQuery, ListUrls, QrQuery,
+    StatsQuery, TestRules, UnlockUrl, UpdateUrl, Url, UrlKey, User, Visitor,
+};
 use axum::{
-    extract::{Path, State},
-    response::IntoResponse,
-    Extension, Json,
+    body::{Body, Bytes},
+    extract::{ConnectInfo, FromRequest, Multipart, Path, Query, RawQuery, Request, State},
+    http::{header, HeaderMap},
+    response::{Html, IntoResponse, Response},
+    Extension, Form, Json,
 };
+use ipnet::IpNet;
 use reqwest::StatusCode;
+use std::net::{IpAddr, SocketAddr};
+
+// visitors keep their variant for 30 days
+const VARIANT_COOKIE_MAX_AGE: u64 = 30 * 24 * 3600;
 
 pub async fn shorten_handler(
     Extension(user): Extension<User>,
@@ -16,21 +27,540 @@ pub async fn shorten_handler(
     Ok((StatusCode::OK, Json(id)))
 }
 
+// accepts a json array, newline delimited json or a csv file uploaded as multipart form
+pub async fn bulk_shorten_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    req: Request,
+) -> Result<impl IntoResponse, AppError> {
+    let content_type = header_value(req.headers(), header::CONTENT_TYPE).unwrap_or_default();
+
+    let input = if content_type.starts_with("multipart/form-data") {
+        let mut multipart = Multipart::from_request(req, &state)
+            .await
+            .map_err(|e| AppError::UserError(e.body_text()))?;
+        let field = multipart
+            .next_field()
+            .await
+            .map_err(|e| AppError::UserError(e.body_text()))?
+            .ok_or_else(|| AppError::UserError("csv file is missing".to_string()))?;
+        let data = field
+            .bytes()
+            .await
+            .map_err(|e| AppError::UserError(e.body_text()))?;
+        parse_csv(&data)?
+    } else {
+        let data = Bytes::from_request(req, &state)
+            .await
+            .map_err(|e| AppError::UserError(e.body_text()))?;
+        if content_type.starts_with("application/x-ndjson") {
+            parse_ndjson(&data)?
+        } else {
+            parse_json(&data)?
+        }
+    };
+
+    let ret = state.bulk_shorten(user.id, input).await?;
+
+    Ok((StatusCode::OK, Json(ret)))
+}
+
 pub async fn get_all_urls_handler(
+    Query(input): Query<ListUrls>,
     Extension(user): Extension<User>,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, AppError> {
-    let ret = state.get_all_url(user.id).await?;
+    let ret = state.get_all_url(user.id, input).await?;
 
     Ok((StatusCode::OK, Json(ret)))
 }
 
-pub async fn redirect_handler(
+pub async fn export_urls_handler(
+    Query(query): Query<ExportQuery>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let format = query.format;
+    let body = Body::from_stream(state.export_urls(user.id, query));
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"urls.{}\"", format.extension()),
+            ),
+        ],
+        body,
+    ))
+}
+
+pub async fn get_url_handler(
+    Path(id): Path<String>,
+    Query(on): Query<DomainQuery>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let url = state
+        .get_url(user.id, UrlKey::new(&id, on.domain.as_deref()))
+        .await?;
+
+    Ok((StatusCode::OK, Json(url)))
+}
+
+pub async fn update_url_handler(
     Path(id): Path<String>,
+    Query(on): Query<DomainQuery>,
     Extension(user): Extension<User>,
     State(state): State<AppState>,
+    Json(input): Json<UpdateUrl>,
 ) -> Result<impl IntoResponse, AppError> {
-    let url = state.get_url(user.id, id).await?;
+    let url = state
+        .update_url(user.id, UrlKey::new(&id, on.domain.as_deref()), input)
+        .await?;
 
     Ok((StatusCode::OK, Json(url)))
 }
+
+pub async fn delete_url_handler(
+    Path(id): Path<String>,
+    Query(on): Query<DomainQuery>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .delete_url(user.id, UrlKey::new(&id, on.domain.as_deref()))
+        .await?;
+
+    Ok(StatusCode::OK)
+}
+
+pub async fn get_url_stats_handler(
+    Path(id): Path<String>,
+    Query(on): Query<DomainQuery>,
+    Query(query): Query<StatsQuery>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let stats = state
+        .get_url_stats(
+            user.id,
+            UrlKey::new(&id, on.domain.as_deref()),
+            query.bucket,
+        )
+        .await?;
+
+    Ok((StatusCode::OK, Json(stats)))
+}
+
+pub async fn list_broken_urls_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let urls = state.list_broken_urls(user.id).await?;
+
+    Ok((StatusCode::OK, Json(urls)))
+}
+
+pub async fn get_url_qr_handler(
+    Path(id): Path<String>,
+    Query(on): Query<DomainQuery>,
+    Query(query): Query<QrQuery>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let url = state
+        .get_url(user.id, UrlKey::new(&id, on.domain.as_deref()))
+        .await?;
+    let body = qr::render(&state.link(&url).await?, &query)?;
+
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, query.format.content_type())],
+        body,
+    ))
+}
+
+// fetch the preview of the destination again
+pub async fn refresh_preview_handler(
+    Path(id): Path<String>,
+    Query(on): Query<DomainQuery>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let url = state
+        .refresh_url_preview(user.id, UrlKey::new(&id, on.domain.as_deref()))
+        .await?;
+
+    Ok((StatusCode::OK, Json(url)))
+}
+
+pub async fn test_rules_handler(
+    Path(id): Path<String>,
+    Query(on): Query<DomainQuery>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<TestRules>,
+) -> Result<impl IntoResponse, AppError> {
+    let ret = state
+        .test_rules(user.id, UrlKey::new(&id, on.domain.as_deref()), input)
+        .await?;
+
+    Ok((StatusCode::OK, Json(ret)))
+}
+
+pub async fn redirect_handler(
+    Path(id): Path<String>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let domain_id = host_domain(&state, &headers).await?;
+
+    match state.resolve_url_on(domain_id, &id, None).await {
+        Err(AppError::Unauthorized(_)) => Ok(password_form(&id, query.as_deref(), false)),
+        ret => visit(&state, ret, &headers, addr, query.as_deref()),
+    }
+}
+
+// the password form of a protected url posts here
+pub async fn unlock_handler(
+    Path(id): Path<String>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<AppState>,
+    Form(input): Form<UnlockUrl>,
+) -> Result<Response, AppError> {
+    let domain_id = host_domain(&state, &headers).await?;
+
+    // every attempt is counted before the password is checked so parallel guesses can't slip
+    // through, a right one gives the attempts back
+    let link_key = format!("{}@{}", id, domain_id.unwrap_or_default());
+    let ip = client_ip(&state.proxies, &headers, addr);
+    let key = format!(
+        "{}@{}",
+        link_key,
+        ip.map(|ip| ip.to_string()).unwrap_or_default()
+    );
+    state.guesses.try_acquire(&key)?;
+    state.link_guesses.try_acquire(&link_key)?;
+
+    match state
+        .resolve_url_on(domain_id, &id, Some(&input.password))
+        .await
+    {
+        Err(AppError::Unauthorized(_)) => Ok(password_form(&id, query.as_deref(), true)),
+        ret => {
+            state.guesses.reset(&key);
+            state.link_guesses.reset(&link_key);
+            visit(&state, ret, &headers, addr, query.as_deref())
+        }
+    }
+}
+
+// visits on a verified custom domain resolve in its namespace, any other host uses the default one
+async fn host_domain(state: &AppState, headers: &HeaderMap) -> Result<Option<i64>, AppError> {
+    let Some(host) = request_host(headers) else {
+        return Ok(None);
+    };
+
+    let domain = state.find_domain_by_host(&host).await?;
+
+    Ok(domain.map(|d| d.id))
+}
+
+// the lowercase `Host` of the request without the port
+pub(crate) fn request_host(headers: &HeaderMap) -> Option<String> {
+    let host = header_value(headers, header::HOST)?;
+    let host = host
+        .rsplit_once(':')
+        .filter(|(_, port)| port.chars().all(|c| c.is_ascii_digit()))
+        .map_or(host.as_str(), |(host, _)| host)
+        .trim_end_matches('.')
+        .to_ascii_lowercase();
+
+    Some(host)
+}
+
+// redirect to the resolved url and record the click
+fn visit(
+    state: &AppState,
+    ret: Result<Url, AppError>,
+    headers: &HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    query: Option<&str>,
+) -> Result<Response, AppError> {
+    let url = match ret {
+        Ok(url) => url,
+        Err(AppError::Gone(e)) => match &state.config.shortener.expired_fallback {
+            Some(fallback) => {
+                return Ok(
+                    (StatusCode::FOUND, [(header::LOCATION, fallback.clone())]).into_response()
+                )
+            }
+            None => return Err(AppError::Gone(e)),
+        },
+        Err(e) => return Err(e),
+    };
+
+    let ip = client_ip(&state.proxies, headers, addr);
+
+    let mut click = Click::new(url.id);
+    click.referrer = header_value(headers, header::REFERER);
+    click.user_agent = header_value(headers, header::USER_AGENT);
+    click.accept_language = header_value(headers, header::ACCEPT_LANGUAGE);
+    click.ip_hash = ip.map(|ip| hash_ip(&state.config.analytics.ip_salt, &ip.to_string()));
+
+    let visitor = Visitor {
+        user_agent: click.user_agent.clone(),
+        accept_language: click.accept_language.clone(),
+        country: ip.and_then(|ip| state.country(ip)),
+        time: click.created_at,
+    };
+    let cookie = variant_cookie(&url.short);
+    let sticky = cookie_value(headers, &cookie);
+    let destination = url.destination(&visitor, sticky.as_deref());
+    click.variant = destination.variant.map(|v| v.to_string());
+
+    let status = StatusCode::from_u16(url.redirect_status as u16).unwrap_or(StatusCode::FOUND);
+    let location = url.location(destination.url, query);
+    let mut res = match url.app_link(&visitor) {
+        Some(app) => app_interstitial(app, &location, state.config.apps.fallback_delay),
+        None => (status, [(header::LOCATION, location)]).into_response(),
+    };
+
+    // variant names only use cookie safe characters
+    if let Some(variant) = destination.variant.filter(|_| url.sticky) {
+        let value = format!(
+            "{}={}; Path=/{}; Max-Age={}; HttpOnly; SameSite=Lax",
+            cookie, variant, url.short, VARIANT_COOKIE_MAX_AGE
+        );
+        if let Ok(value) = value.parse() {
+            res.headers_mut().insert(header::SET_COOKIE, value);
+        }
+    }
+
+    state.record_click(click);
+
+    Ok(res)
+}
+
+// the short code is safe to embed, aliases and generated codes only use url safe characters
+fn password_form(id: &str, query: Option<&str>, wrong: bool) -> Response {
+    // the query is kept for urls passing it on to the destination
+    let action = match query {
+        Some(query) => format!("/{}?{}", id, escape_html(query)),
+        None => format!("/{}", id),
+    };
+    let error = if wrong {
+        "<p>Wrong password, try again.</p>"
+    } else {
+        ""
+    };
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Password required</title></head>
+<body>
+<form method="post" action="{action}">
+<p>This link is protected, enter the password to continue.</p>
+{error}
+<input type="password" name="password" autofocus required>
+<button type="submit">Continue</button>
+</form>
+</body>
+</html>
+"#
+    );
+
+    (StatusCode::UNAUTHORIZED, Html(body)).into_response()
+}
+
+// tries to open the app and goes on to the web url when it is still visible after `delay` ms
+fn app_interstitial(app: &str, fallback: &str, delay: u64) -> Response {
+    let href = escape_html(fallback);
+    let app = script_string(app);
+    let fallback = script_string(fallback);
+    let refresh = delay.div_ceil(1000) + 1;
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<noscript><meta http-equiv="refresh" content="0;url={href}"></noscript>
+<meta http-equiv="refresh" content="{refresh};url={href}">
+<title>Opening the app</title>
+</head>
+<body>
+<p>Opening the app, <a href="{href}">continue in the browser</a> if nothing happens.</p>
+<script>
+setTimeout(function () {{
+  if (!document.hidden) window.location.replace({fallback});
+}}, {delay});
+window.location.href = {app};
+</script>
+</body>
+</html>
+"#
+    );
+
+    (
+        StatusCode::OK,
+        [(header::CACHE_CONTROL, "no-store")],
+        Html(body),
+    )
+        .into_response()
+}
+
+// a json string is a valid script string once `<` can not close the script tag
+fn script_string(s: &str) -> String {
+    serde_json::Value::from(s)
+        .to_string()
+        .replace('<', "\\u003c")
+}
+
+pub(crate) fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn header_value(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+fn variant_cookie(short: &str) -> String {
+    format!("v_{}", short)
+}
+
+fn cookie_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v.to_string())
+}
+
+// the peer address, or behind trusted proxies the last X-Forwarded-For address they did not add,
+// the addresses before it are set by the client
+fn client_ip(
+    proxies: &[IpNet],
+    headers: &HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
+) -> Option<IpAddr> {
+    let peer = addr.map(|ConnectInfo(addr)| addr.ip())?;
+    let trusted = |ip: &IpAddr| proxies.iter().any(|net| net.contains(ip));
+
+    if !trusted(&peer) {
+        return Some(peer);
+    }
+
+    let forwarded: Vec<&str> = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+
+    for v in forwarded.into_iter().rev() {
+        match v.trim().parse::<IpAddr>() {
+            Ok(ip) if trusted(&ip) => continue,
+            Ok(ip) => return Some(ip),
+            Err(_) => break,
+        }
+    }
+
+    Some(peer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn unlock_should_not_lock_out_other_clients() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.password = Some("secret".to_string());
+        let id = state.shorten(user.id, input).await?;
+
+        let unlock = |ip: &str, password: &str| {
+            let addr = SocketAddr::new(ip.parse().expect("ip should be valid"), 80);
+            unlock_handler(
+                Path(id.output.clone()),
+                RawQuery(None),
+                HeaderMap::new(),
+                Some(ConnectInfo(addr)),
+                State(state.clone()),
+                Form(UnlockUrl {
+                    password: password.to_string(),
+                }),
+            )
+        };
+
+        for _ in 0..state.config.shortener.password_attempts {
+            let res = unlock("1.1.1.1", "wrong").await?;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert!(matches!(
+            unlock("1.1.1.1", "secret").await,
+            Err(AppError::TooManyRequests(_))
+        ));
+
+        let res = unlock("2.2.2.2", "secret").await?;
+        assert_eq!(res.status(), StatusCode::FOUND);
+
+        Ok(())
+    }
+
+    #[test]
+    fn client_ip_should_work() {
+        let proxies: Vec<IpNet> = vec!["10.0.0.0/8".parse().expect("network should be valid")];
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-Forwarded-For",
+            "1.1.1.1, 2.2.2.2, 10.0.0.2"
+                .parse()
+                .expect("header should be valid"),
+        );
+        let addr = |ip: &str| {
+            Some(ConnectInfo(SocketAddr::new(
+                ip.parse().expect("ip should be valid"),
+                80,
+            )))
+        };
+
+        // the client can not pick its address without a trusted proxy in front
+        assert_eq!(
+            client_ip(&proxies, &headers, addr("3.3.3.3")),
+            "3.3.3.3".parse().ok()
+        );
+        assert_eq!(
+            client_ip(&[], &headers, addr("10.0.0.1")),
+            "10.0.0.1".parse().ok()
+        );
+        // the address before the trusted ones, `1.1.1.1` is whatever the client sent
+        assert_eq!(
+            client_ip(&proxies, &headers, addr("10.0.0.1")),
+            "2.2.2.2".parse().ok()
+        );
+        assert_eq!(client_ip(&proxies, &headers, None), None);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{AppError, AppState, ChangeUser, CreateUser, LoginUser, User};
+use crate::{AppError, AppState, ChangeUser, CreateUser, LoginUser, TokenClaims, User};
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
 use serde::{Deserialize, Serialize};
 
@@ -20,10 +20,18 @@ pub async fn login_handler(
     Json(input): Json<LoginUser>,
 ) -> Result<impl IntoResponse, AppError> {
     let user = state.login_user(input).await?;
-    let token = state.ek.sign(user)?;
+    let token = state.ek.sign(&user)?;
     Ok((StatusCode::OK, Json(AuthOutput { token })))
 }
 
+pub async fn logout_handler(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_session(claims.uid, &claims.sid).await?;
+    Ok(StatusCode::OK)
+}
+
 pub async fn delete_user_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
@@ -38,6 +46,6 @@ pub async fn change_user_message_handler(
     Json(input): Json<ChangeUser>,
 ) -> Result<impl IntoResponse, AppError> {
     let user = state.change_user_message(user, input).await?;
-    let token = state.ek.sign(user)?;
+    let token = state.ek.sign(&user)?;
     Ok((StatusCode::OK, Json(AuthOutput { token })))
 }
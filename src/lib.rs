@@ -1,28 +1,67 @@
 mod config;
+mod dns;
 mod error;
+mod generator;
+mod geoip;
 mod handlers;
+mod limiter;
 mod middleware;
 mod models;
+mod preview;
+mod qr;
+mod screening;
+mod targeting;
 mod util;
+mod validation;
 
 use axum::{
     middleware::from_fn_with_state,
-    routing::{delete, get, post},
+    routing::{delete, get, patch, post, put},
     Router,
 };
+use ipnet::IpNet;
 use sqlx::{Executor, PgPool};
 use sqlx_db_tester::TestPg;
-use std::{ops::Deref, path::Path, sync::Arc};
+use std::{ops::Deref, path::Path, sync::Arc, time::Duration};
+use tokio::sync::mpsc::Sender;
 
-pub use config::AppConfig;
+pub use config::{
+    AnalyticsConfig, AppConfig, AppsConfig, DomainsConfig, HealthConfig, PreviewConfig,
+    ScreeningConfig, ShortenerConfig, TargetingConfig, UrlUniqueness, ValidationConfig,
+};
+pub use dns::{DohResolver, FakeResolver, TxtResolver};
 pub use error::AppError;
+pub use generator::{ShortGenerator, Strategy};
+pub use geoip::GeoIp;
 pub use handlers::{
-    change_user_message_handler, create_user_handler, delete_user_handler, get_all_urls_handler,
-    login_handler, redirect_handler, shorten_handler,
+    add_block_rule_handler, apple_app_site_association_handler, asset_links_handler,
+    bulk_shorten_handler, change_user_message_handler, create_domain_handler, create_tag_handler,
+    create_user_handler, delete_block_rule_handler, delete_domain_handler, delete_profile_handler,
+    delete_tag_handler, delete_url_handler, delete_user_handler, enable_url_handler,
+    export_urls_handler, get_all_urls_handler, get_profile_handler, get_url_handler,
+    get_url_qr_handler, get_url_stats_handler, get_url_tags_handler, list_block_rules_handler,
+    list_broken_urls_handler, list_domains_handler, list_tags_handler, login_handler,
+    logout_handler, profile_page_handler, redirect_handler, refresh_preview_handler,
+    rename_tag_handler, rescan_urls_handler, set_domain_apps_handler, set_url_tags_handler,
+    shorten_handler, test_rules_handler, unlock_handler, update_profile_handler,
+    update_url_handler, verify_domain_handler,
+};
+pub use limiter::GuessLimiter;
+pub use middleware::{verify_admin, verify_token};
+pub use models::{
+    AssignTags, BlockKind, BlockRule, Bucket, BulkItem, BulkOutput, ChangeUser, Click,
+    CreateBlockRule, CreateDomain, CreateTag, CreateUrl, CreateUser, Destination, Domain,
+    DomainApps, DomainQuery, ExportFormat, ExportQuery, ExportRow, ListTags, ListUrls, LoginUser,
+    Output, Profile, ProfileLink, ProfilePage, RenameTag, RescanOutput, RuleMatch, SortOrder,
+    StatsQuery, Tag, TagKind, TestRules, UnlockUrl, UpdateProfile, UpdateProfileLink, UpdateUrl,
+    Url, UrlKey, UrlList, UrlSort, UrlStats, User, Utm,
 };
-pub use middleware::verify_token;
-pub use models::{ChangeUser, CreateUrl, CreateUser, LoginUser, MoreOutput, Output, Url, User};
-pub use util::{DecodingKey, EncodingKey};
+pub use preview::{Preview, Previewer};
+pub use qr::{QrFormat, QrLevel, QrQuery};
+pub use screening::{Blocklist, HashPrefixList, Screener};
+pub use targeting::{AppLinks, Device, HourRange, Rule, Variant, Visitor};
+pub use util::{DecodingKey, EncodingKey, TokenClaims};
+pub use validation::{merge_query, normalize_url, UrlError};
 
 pub const ADDR: &str = "127.0.0.1:";
 
@@ -36,18 +75,83 @@ pub struct AppStateInner {
     pub pool: PgPool,
     pub ek: EncodingKey,
     pub dk: DecodingKey,
+    pub generator: ShortGenerator,
+    pub clicks: Sender<Click>,
+    pub blocklist: Arc<Blocklist>,
+    pub screeners: Vec<Arc<dyn Screener>>,
+    // wrong passwords per client and url
+    pub guesses: GuessLimiter,
+    // wrong passwords per url whatever the client
+    pub link_guesses: GuessLimiter,
+    pub geoip: Option<GeoIp>,
+    pub previewer: Previewer,
+    pub resolver: Arc<dyn TxtResolver>,
+    pub proxies: Vec<IpNet>,
 }
 
 pub fn get_router(state: AppState) -> Result<Router, AppError> {
+    let admin = Router::new()
+        .route(
+            "/blocklist",
+            get(list_block_rules_handler).post(add_block_rule_handler),
+        )
+        .route("/blocklist/rescan", post(rescan_urls_handler))
+        .route("/blocklist/:id", delete(delete_block_rule_handler))
+        .route("/urls/:id/enable", post(enable_url_handler))
+        .layer(from_fn_with_state(state.clone(), verify_admin));
+
     let app = Router::new()
+        .nest("/admin", admin)
+        .route("/logout", post(logout_handler))
         .route("/delete", delete(delete_user_handler))
         .route("/change", post(change_user_message_handler))
-        .route("/:id", get(redirect_handler))
         .route("/urls", get(get_all_urls_handler))
+        .route("/urls/export", get(export_urls_handler))
+        .route("/urls/broken", get(list_broken_urls_handler))
+        .route(
+            "/urls/:id",
+            get(get_url_handler)
+                .patch(update_url_handler)
+                .delete(delete_url_handler),
+        )
+        .route("/urls/:id/stats", get(get_url_stats_handler))
+        .route("/urls/:id/qr", get(get_url_qr_handler))
+        .route("/urls/:id/preview", post(refresh_preview_handler))
+        .route(
+            "/urls/:id/tags",
+            get(get_url_tags_handler).put(set_url_tags_handler),
+        )
+        .route("/tags", get(list_tags_handler).post(create_tag_handler))
+        .route(
+            "/tags/:id",
+            patch(rename_tag_handler).delete(delete_tag_handler),
+        )
+        .route("/urls/:id/rules/test", post(test_rules_handler))
+        .route(
+            "/domains",
+            get(list_domains_handler).post(create_domain_handler),
+        )
+        .route("/domains/:id", delete(delete_domain_handler))
+        .route(
+            "/profile",
+            get(get_profile_handler)
+                .put(update_profile_handler)
+                .delete(delete_profile_handler),
+        )
+        .route("/domains/:id/verify", post(verify_domain_handler))
+        .route("/domains/:id/apps", put(set_domain_apps_handler))
         .route("/short", post(shorten_handler))
+        .route("/short/bulk", post(bulk_shorten_handler))
         .layer(from_fn_with_state(state.clone(), verify_token))
         .route("/login", post(login_handler))
         .route("/signup", post(create_user_handler))
+        .route(
+            "/.well-known/apple-app-site-association",
+            get(apple_app_site_association_handler),
+        )
+        .route("/.well-known/assetlinks.json", get(asset_links_handler))
+        .route("/@:username", get(profile_page_handler))
+        .route("/:id", get(redirect_handler).post(unlock_handler))
         .with_state(state);
 
     Ok(app)
@@ -58,23 +162,93 @@ impl AppState {
         let config = AppConfig::new()?;
         let ek = EncodingKey::new(&config.auth.ek)?;
         let dk = DecodingKey::new(&config.auth.dk)?;
+        let generator = ShortGenerator::new(&config.shortener)?;
+
+        let blocklist = Arc::new(Blocklist::default());
+        let screeners = screening::build_screeners(&config.screening, blocklist.clone())?;
+        let guesses = GuessLimiter::new(
+            config.shortener.password_attempts,
+            Duration::from_secs(config.shortener.password_window),
+        );
+        let link_guesses = GuessLimiter::new(
+            config.shortener.password_link_attempts,
+            Duration::from_secs(config.shortener.password_window),
+        );
+        let geoip = config
+            .targeting
+            .geoip_file
+            .as_deref()
+            .map(GeoIp::load)
+            .transpose()?;
+        let previewer = Previewer::new(&config.preview)?;
+        let proxies = config.server.trusted_proxies()?;
+        let resolver = Arc::new(DohResolver::new(&config.domains.doh_url)?);
 
         let pool = PgPool::connect(&config.server.db_url).await?;
+        let clicks = models::spawn_click_recorder(pool.clone());
 
-        Ok(Self {
+        let state = Self {
             inner: Arc::new(AppStateInner {
                 config,
                 pool,
                 ek,
                 dk,
+                generator,
+                clicks,
+                blocklist,
+                screeners,
+                guesses,
+                link_guesses,
+                geoip,
+                previewer,
+                resolver,
+                proxies,
             }),
-        })
+        };
+        state.reload_blocklist().await?;
+        state.load_code_length().await?;
+
+        let interval = state.config.screening.rescan_interval;
+        if interval > 0 {
+            models::spawn_blocklist_rescan(state.clone(), Duration::from_secs(interval));
+        }
+
+        let interval = state.config.health.interval;
+        if interval > 0 {
+            models::spawn_health_check(state.clone(), Duration::from_secs(interval));
+        }
+
+        Ok(state)
     }
 
     pub async fn new_for_test() -> Result<(TestPg, Self), AppError> {
-        let config = AppConfig::new()?;
+        let mut config = AppConfig::new()?;
+        // tests never fetch previews in the background and use local stand-in pages
+        config.preview.enabled = false;
+        config.preview.block_private_hosts = false;
         let ek = EncodingKey::new(&config.auth.ek)?;
         let dk = DecodingKey::new(&config.auth.dk)?;
+        let generator = ShortGenerator::new(&config.shortener)?;
+        let blocklist = Arc::new(Blocklist::default());
+        let screeners = screening::build_screeners(&config.screening, blocklist.clone())?;
+        let guesses = GuessLimiter::new(
+            config.shortener.password_attempts,
+            Duration::from_secs(config.shortener.password_window),
+        );
+        let link_guesses = GuessLimiter::new(
+            config.shortener.password_link_attempts,
+            Duration::from_secs(config.shortener.password_window),
+        );
+        let geoip = config
+            .targeting
+            .geoip_file
+            .as_deref()
+            .map(GeoIp::load)
+            .transpose()?;
+        let previewer = Previewer::new(&config.preview)?;
+        let proxies = config.server.trusted_proxies()?;
+        // tests publish their TXT records in memory
+        let resolver = Arc::new(FakeResolver::default());
 
         let post = config
             .server
@@ -97,17 +271,30 @@ impl AppState {
         }
         ts.commit().await.expect("commit transaction failed");
 
-        Ok((
-            tdb,
-            Self {
-                inner: Arc::new(AppStateInner {
-                    config,
-                    pool,
-                    ek,
-                    dk,
-                }),
-            },
-        ))
+        let clicks = models::spawn_click_recorder(pool.clone());
+
+        let state = Self {
+            inner: Arc::new(AppStateInner {
+                config,
+                pool,
+                ek,
+                dk,
+                generator,
+                clicks,
+                blocklist,
+                screeners,
+                guesses,
+                link_guesses,
+                geoip,
+                previewer,
+                resolver,
+                proxies,
+            }),
+        };
+        state.reload_blocklist().await?;
+        state.load_code_length().await?;
+
+        Ok((tdb, state))
     }
 }
 
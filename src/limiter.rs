@@ -0,0 +1,99 @@
+use crate::AppError;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// entries are pruned once the map grows past this size
+const PRUNE_SIZE: usize = 10_000;
+
+// counts guesses per key in a fixed window, the key is locked out once it reaches `max`
+pub struct GuessLimiter {
+    max: u32,
+    window: Duration,
+    entries: Mutex<HashMap<String, (u32, Instant)>>,
+}
+
+impl GuessLimiter {
+    pub fn new(max: u32, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // counts the guess up front under one lock, `reset` gives the attempts back once it is right
+    pub fn try_acquire(&self, key: &str) -> Result<(), AppError> {
+        let mut entries = self.entries.lock().expect("limiter lock poisoned");
+
+        if entries.len() >= PRUNE_SIZE {
+            entries.retain(|_, (_, start)| start.elapsed() < self.window);
+        }
+
+        let now = Instant::now();
+        let entry = entries.entry(key.to_string()).or_insert((0, now));
+        if entry.1.elapsed() >= self.window {
+            *entry = (0, now);
+        }
+        if entry.0 >= self.max {
+            return Err(AppError::TooManyRequests(format!(
+                "{} by guesses reached the limit",
+                key
+            )));
+        }
+        entry.0 += 1;
+
+        Ok(())
+    }
+
+    pub fn reset(&self, key: &str) {
+        self.entries
+            .lock()
+            .expect("limiter lock poisoned")
+            .remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guess_limiter_should_work() {
+        let limiter = GuessLimiter::new(2, Duration::from_millis(50));
+
+        assert!(limiter.try_acquire("a").is_ok());
+        assert!(limiter.try_acquire("a").is_ok());
+        assert!(matches!(
+            limiter.try_acquire("a"),
+            Err(AppError::TooManyRequests(_))
+        ));
+        assert!(limiter.try_acquire("b").is_ok());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(limiter.try_acquire("a").is_ok());
+        assert!(limiter.try_acquire("a").is_ok());
+        limiter.reset("a");
+        assert!(limiter.try_acquire("a").is_ok());
+    }
+
+    #[test]
+    fn parallel_guesses_should_not_pass_the_limit() {
+        let limiter = GuessLimiter::new(5, Duration::from_secs(60));
+
+        let passed = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..20)
+                .map(|_| s.spawn(|| limiter.try_acquire("a").is_ok()))
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("thread should finish"))
+                .filter(|ok| *ok)
+                .count()
+        });
+
+        assert_eq!(passed, 5);
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use my_backend::{get_router, AppState, ADDR};
+use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tracing::{info, level_filters::LevelFilter};
 use tracing_subscriber::{
@@ -20,7 +21,11 @@ async fn main() -> Result<()> {
     let listener = TcpListener::bind(addr).await?;
 
     let app = get_router(state)?;
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
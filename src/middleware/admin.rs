@@ -0,0 +1,81 @@
+use crate::{AppState, User};
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use tracing::warn;
+
+// must run after `verify_token`, which puts the user into the request, admins are granted by
+// setting `users.role` in the database
+pub async fn verify_admin(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    req: Request,
+    next: Next,
+) -> Response {
+    match state.is_admin(user.id).await {
+        Ok(true) => next.run(req).await,
+        Ok(false) => {
+            let msg = format!("{} by user is not an admin", user.email);
+            warn!(msg);
+            (StatusCode::FORBIDDEN, msg).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify_token;
+    use anyhow::Result;
+    use axum::{body::Body, middleware::from_fn_with_state, routing::get, Router};
+    use tower::ServiceExt;
+
+    async fn handler() -> impl IntoResponse {
+        (StatusCode::OK, "OK")
+    }
+
+    #[tokio::test]
+    async fn verify_admin_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let token = state.ek.sign(&user)?;
+
+        let app = Router::new()
+            .route("/", get(handler))
+            .layer(from_fn_with_state(state.clone(), verify_admin))
+            .layer(from_fn_with_state(state.clone(), verify_token))
+            .with_state(state.clone());
+
+        let req = || {
+            Request::builder()
+                .uri("/")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+        };
+
+        // not an admin
+        let res = app.clone().oneshot(req()?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        sqlx::query("UPDATE users SET role = 'admin' WHERE id = $1")
+            .bind(user.id)
+            .execute(&state.pool)
+            .await?;
+
+        // same token, the role is checked on every request
+        let res = app.oneshot(req()?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        Ok(())
+    }
+}
@@ -40,24 +40,51 @@ pub async fn verify_token(State(state): State<AppState>, req: Request, next: Nex
             }
         };
 
-    let req = match state.dk.verify(&token) {
-        Ok(user) => {
-            let mut req = Request::from_parts(parts, body);
-            req.extensions_mut().insert(user);
-            req
-        }
+    let claims = match state.dk.verify(&token) {
+        Ok(claims) => claims,
         Err(e) => {
             let msg = format!("verify token failed: {:?}", e);
             warn!(msg);
             return (StatusCode::FORBIDDEN, msg).into_response();
         }
     };
+
+    // the user is loaded fresh, so renames, password changes and deletions apply at once
+    let user = match state.find_user_by_id(claims.uid).await {
+        Ok(Some(user)) if user.token_version == claims.ver => user,
+        Ok(Some(_)) => {
+            let msg = format!("{} by token is revoked", claims.sid);
+            warn!(msg);
+            return (StatusCode::UNAUTHORIZED, msg).into_response();
+        }
+        Ok(None) => {
+            let msg = format!("{} by user not find", claims.uid);
+            warn!(msg);
+            return (StatusCode::UNAUTHORIZED, msg).into_response();
+        }
+        Err(e) => return e.into_response(),
+    };
+
+    match state.is_session_revoked(&claims.sid).await {
+        Ok(false) => {}
+        Ok(true) => {
+            let msg = format!("{} by session is revoked", claims.sid);
+            warn!(msg);
+            return (StatusCode::UNAUTHORIZED, msg).into_response();
+        }
+        Err(e) => return e.into_response(),
+    }
+
+    let mut req = Request::from_parts(parts, body);
+    req.extensions_mut().insert(user);
+    req.extensions_mut().insert(claims);
     next.run(req).await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ChangeUser;
     use anyhow::Result;
     use axum::{body::Body, http::Request, middleware::from_fn_with_state, routing::get, Router};
     use tower::ServiceExt;
@@ -76,12 +103,12 @@ mod tests {
             .await?
             .expect("user should exists");
 
-        let token = state.ek.sign(user)?;
+        let token = state.ek.sign(&user)?;
 
         let app = Router::new()
             .route("/", get(handler))
             .layer(from_fn_with_state(state.clone(), verify_token))
-            .with_state(state);
+            .with_state(state.clone());
 
         // good token
         let req = Request::builder()
@@ -117,9 +144,58 @@ mod tests {
         let req = Request::builder()
             .uri("/?token=bad-token")
             .body(Body::empty())?;
-        let res = app.oneshot(req).await?;
+        let res = app.clone().oneshot(req).await?;
         assert_eq!(res.status(), StatusCode::FORBIDDEN);
 
+        // a new password revokes the token
+        let input = ChangeUser {
+            email: None,
+            name: None,
+            password: Some("hunter42".to_string()),
+        };
+        let user = state.change_user_message(user, input).await?;
+        let req = || {
+            Request::builder()
+                .uri("/")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+        };
+        let res = app.clone().oneshot(req()?).await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // logging out revokes only that session
+        let token = state.ek.sign(&user)?;
+        let other = state.ek.sign(&user)?;
+        let claims = state.dk.verify(&token)?;
+        state.revoke_session(user.id, &claims.sid).await?;
+        let req = Request::builder()
+            .uri("/")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let req = Request::builder()
+            .uri("/")
+            .header("Authorization", format!("Bearer {}", other))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // so does deleting the user
+        let token = state.ek.sign(&user)?;
+        let req = || {
+            Request::builder()
+                .uri("/")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+        };
+        let res = app.clone().oneshot(req()?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        state.delete_user_by_email(&user.email).await?;
+        let res = app.oneshot(req()?).await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
         Ok(())
     }
 }
@@ -1,3 +1,5 @@
+mod admin;
 mod auth;
 
+pub use admin::verify_admin;
 pub use auth::verify_token;
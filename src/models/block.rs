@@ -0,0 +1,461 @@
+use crate::{screening::compile, AppError, AppState, Url, UrlError};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::time::Duration;
+use tracing::{info, warn};
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockRule {
+    pub id: i64,
+    pub kind: BlockKind,
+    pub pattern: String,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum BlockKind {
+    Domain,
+    Regex,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateBlockRule {
+    pub kind: BlockKind,
+    pub pattern: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RescanOutput {
+    pub disabled: u64,
+}
+
+// re-screen existing urls so links whose destination became blocked stop redirecting
+pub fn spawn_blocklist_rescan(state: AppState, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // the first tick completes immediately, the startup load already screened everything
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            match state.rescan_urls().await {
+                Ok(disabled) if disabled > 0 => info!("rescan disabled {} urls", disabled),
+                Ok(_) => {}
+                Err(e) => warn!("rescan urls failed: {}", e),
+            }
+        }
+    });
+}
+
+impl AppState {
+    pub async fn list_block_rules(&self) -> Result<Vec<BlockRule>, AppError> {
+        let rules = sqlx::query_as(
+            "
+            SELECT * FROM blocklist ORDER BY id
+            ",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rules)
+    }
+
+    pub async fn add_block_rule(&self, input: CreateBlockRule) -> Result<BlockRule, AppError> {
+        let pattern = input.validate()?;
+
+        let rule = sqlx::query_as(
+            "
+            INSERT INTO blocklist (kind, pattern, reason) VALUES ($1, $2, $3)
+            ON CONFLICT (kind, pattern) DO NOTHING RETURNING *
+            ",
+        )
+        .bind(input.kind)
+        .bind(&pattern)
+        .bind(input.reason)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::Conflict(format!("{} by block rule already exists", pattern)))?;
+
+        self.reload_blocklist().await?;
+
+        Ok(rule)
+    }
+
+    // the urls the rule disabled are enabled again, unless another rule still blocks them
+    pub async fn delete_block_rule(&self, id: i64) -> Result<BlockRule, AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        let ids: Vec<i64> = sqlx::query_scalar(
+            "
+            UPDATE urls SET disabled_at = NULL, disabled_reason = NULL, disabled_by = NULL
+            WHERE disabled_by = $1 RETURNING id
+            ",
+        )
+        .bind(id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let rule = sqlx::query_as(
+            "
+            DELETE FROM blocklist WHERE id = $1 RETURNING *
+            ",
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by block rule not find", id)))?;
+
+        tx.commit().await?;
+
+        self.reload_blocklist().await?;
+        if !ids.is_empty() {
+            self.rescan(Some(&ids)).await?;
+        }
+
+        Ok(rule)
+    }
+
+    // an admin override, the rescan skips the url until the owner changes its destinations
+    pub async fn enable_url(&self, id: i64) -> Result<Url, AppError> {
+        let url = sqlx::query_as(
+            "
+            UPDATE urls
+            SET disabled_at = NULL, disabled_reason = NULL, disabled_by = NULL, screening_exempt = TRUE
+            WHERE id = $1 RETURNING *
+            ",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by url not find", id)))?;
+
+        Ok(url)
+    }
+
+    pub async fn reload_blocklist(&self) -> Result<(), AppError> {
+        let rules = self.list_block_rules().await?;
+        self.blocklist.reload(&rules)
+    }
+
+    // run every screener on a canonical url, the first match rejects it
+    pub fn screen_url(&self, url: &str) -> Result<(), AppError> {
+        let parsed = url::Url::parse(url)
+            .map_err(|e| UrlError::Malformed(url.to_string(), e.to_string()))?;
+
+        match self.screeners.iter().find_map(|s| s.screen(&parsed)) {
+            Some(reason) => Err(UrlError::Blocked(reason).into()),
+            None => Ok(()),
+        }
+    }
+
+    // disable the enabled urls which no longer pass screening, returns how many were disabled
+    pub async fn rescan_urls(&self) -> Result<u64, AppError> {
+        self.reload_blocklist().await?;
+        self.rescan(None).await
+    }
+
+    // screen the enabled urls, all of them without `ids`
+    async fn rescan(&self, ids: Option<&[i64]>) -> Result<u64, AppError> {
+        let mut blocked = vec![];
+        {
+            let mut rows = sqlx::query_as::<_, Url>(
+                "
+                SELECT * FROM urls
+                WHERE disabled_at IS NULL AND NOT screening_exempt
+                    AND ($1::BIGINT[] IS NULL OR id = ANY($1))
+                ORDER BY id
+                ",
+            )
+            .bind(ids)
+            .fetch(&self.pool);
+
+            while let Some(url) = rows.try_next().await? {
+                // the targets of the rules, variants and app links are screened as well
+                let found = std::iter::once(&url.url)
+                    .chain(url.rules.iter().map(|rule| &rule.url))
+                    .chain(url.variants.iter().map(|v| &v.url))
+                    .chain(url.app_links.iter().flat_map(|apps| apps.web_uris()))
+                    .find_map(|target| self.blocked_by(target));
+                if let Some((rule_id, reason)) = found {
+                    blocked.push((url.id, rule_id, reason));
+                }
+            }
+        }
+
+        let mut disabled = 0;
+        for (id, rule_id, reason) in blocked {
+            disabled += sqlx::query(
+                "
+                UPDATE urls SET disabled_at = CURRENT_TIMESTAMP, disabled_reason = $2, disabled_by = $3
+                WHERE id = $1 AND disabled_at IS NULL
+                ",
+            )
+            .bind(id)
+            .bind(reason)
+            .bind(rule_id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        }
+
+        Ok(disabled)
+    }
+
+    // the reason a target is blocked and the block rule behind it, no rule for the other screeners
+    fn blocked_by(&self, target: &str) -> Option<(Option<i64>, String)> {
+        let parsed = url::Url::parse(target).ok()?;
+        if let Some((id, reason)) = self.blocklist.find(&parsed) {
+            return Some((Some(id), reason));
+        }
+
+        match self.screen_url(target) {
+            Err(AppError::InvalidUrl(UrlError::Blocked(reason))) => Some((None, reason)),
+            _ => None,
+        }
+    }
+}
+
+impl CreateBlockRule {
+    // returns the pattern as it is stored
+    fn validate(&self) -> Result<String, AppError> {
+        let pattern = self.pattern.trim();
+
+        match self.kind {
+            BlockKind::Domain => {
+                let domain = pattern.trim_end_matches('.').to_ascii_lowercase();
+                match url::Host::parse(&domain) {
+                    Ok(url::Host::Domain(host)) if !host.is_empty() => Ok(host),
+                    _ => Err(AppError::UserError(format!(
+                        "{} by domain is invalid",
+                        pattern
+                    ))),
+                }
+            }
+            BlockKind::Regex => {
+                compile(pattern)?;
+                Ok(pattern.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AppLinks, CreateUrl};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn block_rule_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let input = CreateBlockRule {
+            kind: BlockKind::Domain,
+            pattern: "Evil.COM".to_string(),
+            reason: Some("phishing".to_string()),
+        };
+        let rule = state.add_block_rule(input).await?;
+
+        assert_eq!(rule.pattern, "evil.com");
+
+        let input = CreateBlockRule {
+            kind: BlockKind::Domain,
+            pattern: "evil.com".to_string(),
+            reason: None,
+        };
+        let ret = state.add_block_rule(input).await;
+
+        assert!(matches!(ret, Err(AppError::Conflict(_))));
+
+        let input = CreateBlockRule {
+            kind: BlockKind::Regex,
+            pattern: "(".to_string(),
+            reason: None,
+        };
+        assert!(state.add_block_rule(input).await.is_err());
+
+        let input = CreateBlockRule {
+            kind: BlockKind::Domain,
+            pattern: "https://evil.com/".to_string(),
+            reason: None,
+        };
+        assert!(state.add_block_rule(input).await.is_err());
+
+        assert!(state.screen_url("https://www.evil.com/").is_err());
+
+        let rules = state.list_block_rules().await?;
+
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].kind, BlockKind::Domain);
+
+        state.delete_block_rule(rule.id).await?;
+
+        assert!(state.screen_url("https://www.evil.com/").is_ok());
+        assert!(matches!(
+            state.delete_block_rule(rule.id).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rescan_urls_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let evil = state
+            .shorten(user.id, CreateUrl::new("https://login.evil.com/"))
+            .await?;
+        let good = state
+            .shorten(user.id, CreateUrl::new("https://www.baidu.com/"))
+            .await?;
+
+        // inserted behind the back of the cache, only the rescan picks it up
+        sqlx::query("INSERT INTO blocklist (kind, pattern) VALUES ('domain', 'evil.com')")
+            .execute(&state.pool)
+            .await?;
+
+        assert_eq!(state.rescan_urls().await?, 1);
+        assert_eq!(state.rescan_urls().await?, 0);
+
+        let url = state.get_url(user.id, &evil.output).await?;
+
+        assert!(url.disabled_at.is_some());
+        assert_eq!(
+            url.disabled_reason.as_deref(),
+            Some("evil.com by domain is blocked")
+        );
+        assert!(matches!(
+            state.resolve_url(&evil.output, None).await,
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(state.resolve_url(&good.output, None).await.is_ok());
+
+        let ret = state
+            .shorten(user.id, CreateUrl::new("https://evil.com/other"))
+            .await;
+
+        assert!(matches!(
+            ret,
+            Err(AppError::InvalidUrl(UrlError::Blocked(_)))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rescan_urls_should_screen_app_links() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let input = CreateUrl {
+            app_links: Some(AppLinks {
+                ios: Some("HTTPS://App.Evil.com/item".to_string()),
+                android: Some("evil://item".to_string()),
+            }),
+            ..CreateUrl::new("https://www.baidu.com/")
+        };
+        let id = state.shorten(user.id, input.clone()).await?;
+
+        let url = state.get_url(user.id, &id.output).await?;
+        let apps = url.app_links.expect("app links should exists").0;
+
+        assert_eq!(apps.ios.as_deref(), Some("https://app.evil.com/item"));
+        assert_eq!(apps.android.as_deref(), Some("evil://item"));
+
+        sqlx::query("INSERT INTO blocklist (kind, pattern) VALUES ('domain', 'evil.com')")
+            .execute(&state.pool)
+            .await?;
+
+        assert_eq!(state.rescan_urls().await?, 1);
+        assert!(matches!(
+            state.shorten(user.id, input).await,
+            Err(AppError::InvalidUrl(UrlError::Blocked(_)))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_block_rule_should_enable_urls() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let evil = state
+            .shorten(user.id, CreateUrl::new("https://login.evil.com/"))
+            .await?;
+        let worse = state
+            .shorten(user.id, CreateUrl::new("https://login.worse.com/"))
+            .await?;
+
+        let domain = |pattern: &str| CreateBlockRule {
+            kind: BlockKind::Domain,
+            pattern: pattern.to_string(),
+            reason: None,
+        };
+        let rule = state.add_block_rule(domain("evil.com")).await?;
+        state.add_block_rule(domain("worse.com")).await?;
+        let regex = state
+            .add_block_rule(CreateBlockRule {
+                kind: BlockKind::Regex,
+                pattern: "login".to_string(),
+                reason: None,
+            })
+            .await?;
+
+        assert_eq!(state.rescan_urls().await?, 2);
+
+        // `worse.com` still blocks its url once the pattern is gone
+        state.delete_block_rule(regex.id).await?;
+        state.delete_block_rule(rule.id).await?;
+
+        let url = state.get_url(user.id, &evil.output).await?;
+
+        assert!(url.disabled_at.is_none());
+        assert!(url.disabled_reason.is_none());
+        assert!(state.resolve_url(&evil.output, None).await.is_ok());
+
+        let url = state.get_url(user.id, &worse.output).await?;
+
+        assert_eq!(
+            url.disabled_reason.as_deref(),
+            Some("worse.com by domain is blocked")
+        );
+
+        let url = state.enable_url(url.id).await?;
+
+        assert!(url.disabled_at.is_none());
+        // the override outlives the next rescan
+        assert_eq!(state.rescan_urls().await?, 0);
+        let url = state.get_url(user.id, &worse.output).await?;
+        assert!(url.disabled_at.is_none());
+        assert!(state.resolve_url(&worse.output, None).await.is_ok());
+        assert!(matches!(
+            state.enable_url(0).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        Ok(())
+    }
+}
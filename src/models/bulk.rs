@@ -0,0 +1,197 @@
+use crate::{AppError, AppState, CreateUrl};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BulkItem {
+    pub index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub short: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BulkOutput {
+    pub items: Vec<BulkItem>,
+}
+
+// a row which failed to parse keeps its error so it is reported with the other results
+pub type BulkInput = Vec<Result<CreateUrl, String>>;
+
+pub fn parse_json(data: &[u8]) -> Result<BulkInput, AppError> {
+    let items: Vec<serde_json::Value> = serde_json::from_slice(data)
+        .map_err(|e| AppError::UserError(format!("parse json array failed: {}", e)))?;
+
+    Ok(items
+        .into_iter()
+        .map(|v| serde_json::from_value(v).map_err(|e| e.to_string()))
+        .collect())
+}
+
+pub fn parse_ndjson(data: &[u8]) -> Result<BulkInput, AppError> {
+    let data = std::str::from_utf8(data)
+        .map_err(|e| AppError::UserError(format!("parse ndjson failed: {}", e)))?;
+
+    Ok(data
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| e.to_string()))
+        .collect())
+}
+
+// csv with a header row, columns are named after the fields of `CreateUrl`
+pub fn parse_csv(data: &[u8]) -> Result<BulkInput, AppError> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(data);
+
+    Ok(rdr
+        .deserialize()
+        .map(|row| row.map_err(|e| e.to_string()))
+        .collect())
+}
+
+impl AppState {
+    // all urls are created in one transaction, a failed item only rolls back its own savepoint
+    pub async fn bulk_shorten(
+        &self,
+        user_id: i64,
+        input: BulkInput,
+    ) -> Result<BulkOutput, AppError> {
+        let limit = self.config.shortener.bulk_limit;
+        if input.len() > limit {
+            return Err(AppError::UserError(format!(
+                "{} urls exceed the bulk limit {}",
+                input.len(),
+                limit
+            )));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let mut items = Vec::with_capacity(input.len());
+        let mut ids = vec![];
+
+        for (index, item) in input.into_iter().enumerate() {
+            let item = match item {
+                Ok(item) => item,
+                Err(e) => {
+                    items.push(BulkItem::failed(index, e));
+                    continue;
+                }
+            };
+
+            let mut sp = sqlx::Connection::begin(&mut *tx).await?;
+            match self.shorten_with(&mut sp, user_id, &item).await {
+                Ok(url) => {
+                    sp.commit().await?;
+                    ids.push(url.id);
+                    items.push(BulkItem::created(index, url.short));
+                }
+                Err(e) => {
+                    sp.rollback().await?;
+                    warn!("bulk shorten item {} failed: {}", index, e);
+                    items.push(BulkItem::failed(index, e.client_message()));
+                }
+            }
+        }
+
+        tx.commit().await?;
+
+        self.queue_previews(ids);
+
+        Ok(BulkOutput { items })
+    }
+}
+
+impl BulkItem {
+    fn created(index: usize, short: String) -> Self {
+        Self {
+            index,
+            short: Some(short),
+            error: None,
+        }
+    }
+
+    fn failed(index: usize, error: String) -> Self {
+        Self {
+            index,
+            short: None,
+            error: Some(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn parse_should_work() -> Result<()> {
+        let data = br#"[{"url": "https://www.baidu.com/"}, {"url": "https://www.360.com/", "alias": "three-six"}, {"alias": "none"}]"#;
+        let ret = parse_json(data)?;
+
+        assert_eq!(ret.len(), 3);
+        assert_eq!(ret[1].as_ref().unwrap().alias.as_deref(), Some("three-six"));
+        assert!(ret[2].is_err());
+
+        let data =
+            b"{\"url\": \"https://www.baidu.com/\"}\n\n{\"url\": \"https://www.360.com/\"}\nbad\n";
+        let ret = parse_ndjson(data)?;
+
+        assert_eq!(ret.len(), 3);
+        assert_eq!(ret[1].as_ref().unwrap().url, "https://www.360.com/");
+        assert!(ret[2].is_err());
+
+        let data =
+            b"url,alias,max_clicks\nhttps://www.baidu.com/,,\nhttps://www.360.com/,three-six,10\nhttps://www.qq.com/,,ten\n";
+        let ret = parse_csv(data)?;
+
+        assert_eq!(ret.len(), 3);
+        assert!(ret[0].as_ref().unwrap().alias.is_none());
+        assert_eq!(ret[1].as_ref().unwrap().max_clicks, Some(10));
+        assert!(ret[2].is_err());
+
+        assert!(parse_json(b"{}").is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bulk_shorten_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let data =
+            b"url,alias\nhttps://www.baidu.com/,baidu\nhttps://www.360.com/,baidu\nhttps://www.qq.com/,\nhttps://www.qq.com/,login\n";
+        let mut input = parse_csv(data)?;
+        input.push(Err("bad row".to_string()));
+
+        let ret = state.bulk_shorten(user.id, input).await?;
+
+        assert_eq!(ret.items.len(), 5);
+        assert_eq!(ret.items[0].short.as_deref(), Some("baidu"));
+        // alias already taken by the first row, told the same way `/short` would
+        assert_eq!(ret.items[1].error.as_deref(), Some("Conflict"));
+        assert!(ret.items[2].short.is_some());
+        assert!(ret.items[3].error.is_some());
+        assert_eq!(ret.items[4].error.as_deref(), Some("bad row"));
+
+        let url = state.get_url(user.id, "baidu").await?;
+
+        assert_eq!(url.url, "https://www.baidu.com/");
+
+        let input = (0..state.config.shortener.bulk_limit + 1)
+            .map(|_| Ok(CreateUrl::new("https://www.baidu.com/")))
+            .collect();
+
+        assert!(state.bulk_shorten(user.id, input).await.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+use crate::{AppError, AppState, UrlKey};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+const CLICK_CHANNEL_SIZE: usize = 1024;
+const TOP_LIMIT: i64 = 10;
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Click {
+    pub url_id: i64,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_hash: Option<String>,
+    pub accept_language: Option<String>,
+    pub created_at: DateTime<Utc>,
+    // the variant served when the url splits its visitors
+    pub variant: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Bucket {
+    Hour,
+    #[default]
+    Day,
+    Week,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatsQuery {
+    #[serde(default)]
+    pub bucket: Bucket,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UrlStats {
+    pub total: i64,
+    pub unique_visitors: i64,
+    pub series: Vec<StatsBucket>,
+    pub top_referrers: Vec<StatsCount>,
+    pub top_user_agents: Vec<StatsCount>,
+    pub variants: Vec<StatsCount>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct StatsBucket {
+    pub time: DateTime<Utc>,
+    pub clicks: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct StatsCount {
+    pub value: String,
+    pub count: i64,
+}
+
+impl Click {
+    pub fn new(url_id: i64) -> Self {
+        Self {
+            url_id,
+            referrer: None,
+            user_agent: None,
+            ip_hash: None,
+            accept_language: None,
+            created_at: Utc::now(),
+            variant: None,
+        }
+    }
+}
+
+impl Bucket {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hour => "hour",
+            Self::Day => "day",
+            Self::Week => "week",
+        }
+    }
+}
+
+// clicks are written by a background task so redirects do not wait for the database
+pub fn spawn_click_recorder(pool: PgPool) -> mpsc::Sender<Click> {
+    let (tx, mut rx) = mpsc::channel::<Click>(CLICK_CHANNEL_SIZE);
+
+    tokio::spawn(async move {
+        while let Some(click) = rx.recv().await {
+            if let Err(e) = insert_click(&pool, &click).await {
+                warn!("record click failed: {}", e);
+            }
+        }
+    });
+
+    tx
+}
+
+pub fn hash_ip(salt: &str, ip: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    // so `salt` + `1.2.3.4` and `salt1` + `.2.3.4` hash differently
+    hasher.update(b"\0");
+    hasher.update(ip.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+async fn insert_click(pool: &PgPool, click: &Click) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+        INSERT INTO clicks (url_id, referrer, user_agent, ip_hash, accept_language, created_at, variant)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ",
+    )
+    .bind(click.url_id)
+    .bind(&click.referrer)
+    .bind(&click.user_agent)
+    .bind(&click.ip_hash)
+    .bind(&click.accept_language)
+    .bind(click.created_at)
+    .bind(&click.variant)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+impl AppState {
+    pub fn record_click(&self, click: Click) {
+        if let Err(e) = self.clicks.try_send(click) {
+            warn!("drop click event: {}", e);
+        }
+    }
+
+    pub async fn get_url_stats<'a>(
+        &self,
+        user_id: i64,
+        key: impl Into<UrlKey<'a>>,
+        bucket: Bucket,
+    ) -> Result<UrlStats, AppError> {
+        let url_id = self.get_url(user_id, key).await?.id;
+
+        let (total, unique_visitors): (i64, i64) = sqlx::query_as(
+            "
+            SELECT COUNT(*), COUNT(DISTINCT ip_hash) FROM clicks WHERE url_id = $1
+            ",
+        )
+        .bind(url_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let series = sqlx::query_as(
+            "
+            SELECT date_trunc($2, created_at) AS time, COUNT(*) AS clicks
+            FROM clicks WHERE url_id = $1 GROUP BY 1 ORDER BY 1
+            ",
+        )
+        .bind(url_id)
+        .bind(bucket.as_str())
+        .fetch_all(&self.pool)
+        .await?;
+
+        let top_referrers = sqlx::query_as(
+            "
+            SELECT referrer AS value, COUNT(*) AS count FROM clicks
+            WHERE url_id = $1 AND referrer IS NOT NULL GROUP BY 1 ORDER BY 2 DESC, 1 LIMIT $2
+            ",
+        )
+        .bind(url_id)
+        .bind(TOP_LIMIT)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let top_user_agents = sqlx::query_as(
+            "
+            SELECT user_agent AS value, COUNT(*) AS count FROM clicks
+            WHERE url_id = $1 AND user_agent IS NOT NULL GROUP BY 1 ORDER BY 2 DESC, 1 LIMIT $2
+            ",
+        )
+        .bind(url_id)
+        .bind(TOP_LIMIT)
+        .fetch_all(&self.pool)
+        .await?;
+
+        // every variant, not only the top ones, so split tests can be compared
+        let variants = sqlx::query_as(
+            "
+            SELECT variant AS value, COUNT(*) AS count FROM clicks
+            WHERE url_id = $1 AND variant IS NOT NULL GROUP BY 1 ORDER BY 1
+            ",
+        )
+        .bind(url_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(UrlStats {
+            total,
+            unique_visitors,
+            series,
+            top_referrers,
+            top_user_agents,
+            variants,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateUrl;
+    use anyhow::Result;
+    use chrono::Duration;
+
+    #[test]
+    fn hash_ip_should_work() {
+        let hash = hash_ip("salt", "127.0.0.1");
+
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_ip("salt", "127.0.0.1"));
+        assert_ne!(hash, hash_ip("pepper", "127.0.0.1"));
+        assert_ne!(hash_ip("salt1", ".2.3.4"), hash_ip("salt", "1.2.3.4"));
+    }
+
+    #[tokio::test]
+    async fn get_url_stats_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let id = state
+            .shorten(user.id, CreateUrl::new("https://www.baidu.com/"))
+            .await?;
+        let url = state
+            .find_url_by_short(&id.output)
+            .await?
+            .expect("url should exists");
+
+        let now = Utc::now();
+        let clicks = [
+            ("1.1.1.1", "google.com", now - Duration::days(1)),
+            ("1.1.1.1", "google.com", now),
+            ("2.2.2.2", "bing.com", now),
+        ];
+        for (ip, referrer, created_at) in clicks {
+            let mut click = Click::new(url.id);
+            click.variant = (ip == "2.2.2.2").then(|| "b".to_string());
+            click.ip_hash = Some(hash_ip("", ip));
+            click.referrer = Some(referrer.to_string());
+            click.user_agent = Some("curl/8.0".to_string());
+            click.created_at = created_at;
+            insert_click(&state.pool, &click).await?;
+        }
+
+        let stats = state
+            .get_url_stats(user.id, &id.output, Bucket::Day)
+            .await?;
+
+        assert_eq!(stats.total, 3);
+        assert_eq!(stats.unique_visitors, 2);
+        assert_eq!(stats.series.len(), 2);
+        assert_eq!(stats.series[1].clicks, 2);
+        assert_eq!(stats.top_referrers[0].value, "google.com");
+        assert_eq!(stats.top_referrers[0].count, 2);
+        assert_eq!(stats.top_user_agents[0].count, 3);
+        assert_eq!(stats.variants.len(), 1);
+        assert_eq!(stats.variants[0].value, "b");
+        assert_eq!(stats.variants[0].count, 1);
+
+        let ret = state.get_url_stats(user.id, "nothing", Bucket::Day).await;
+
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,555 @@
+use crate::{dns::TxtResolver, AppError, AppState, Url};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{types::Json, FromRow};
+
+// the ownership of a domain is proven by `_shortener.{host} TXT "shortener-verify={token}"`
+const TXT_PREFIX: &str = "_shortener";
+const TXT_VALUE: &str = "shortener-verify=";
+const TOKEN_LEN: usize = 32;
+const MAX_APP_FILE: usize = 64 * 1024;
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Domain {
+    pub id: i64,
+    pub user_id: i64,
+    pub host: String,
+    pub token: String,
+    // short urls are only served on the domain once it is verified
+    pub verified_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    // served as `/.well-known/apple-app-site-association` and `/.well-known/assetlinks.json`
+    pub apple_app_site_association: Option<Json<Value>>,
+    pub asset_links: Option<Json<Value>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateDomain {
+    pub host: String,
+}
+
+// replaces both files, a missing one is no longer served
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DomainApps {
+    #[serde(default)]
+    pub apple_app_site_association: Option<Value>,
+    #[serde(default)]
+    pub asset_links: Option<Value>,
+}
+
+impl AppState {
+    pub async fn list_domains(&self, user_id: i64) -> Result<Vec<Domain>, AppError> {
+        let domains = sqlx::query_as(
+            "
+            SELECT * FROM domains WHERE user_id = $1 ORDER BY host
+            ",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(domains)
+    }
+
+    pub async fn create_domain(
+        &self,
+        user_id: i64,
+        input: CreateDomain,
+    ) -> Result<Domain, AppError> {
+        let host = self.normalize_host(&input.host)?;
+        let token = nanoid::nanoid!(TOKEN_LEN);
+
+        let domain = sqlx::query_as(
+            "
+            INSERT INTO domains (user_id, host, token) VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, host) DO NOTHING RETURNING *
+            ",
+        )
+        .bind(user_id)
+        .bind(&host)
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::Conflict(format!("{} by domain already exists", host)))?;
+
+        Ok(domain)
+    }
+
+    pub async fn verify_domain(&self, user_id: i64, id: i64) -> Result<Domain, AppError> {
+        self.verify_domain_with(user_id, id, self.resolver.as_ref())
+            .await
+    }
+
+    // look up the TXT record of the domain and mark it verified when the token is published
+    pub async fn verify_domain_with(
+        &self,
+        user_id: i64,
+        id: i64,
+        resolver: &dyn TxtResolver,
+    ) -> Result<Domain, AppError> {
+        let domain = self.get_domain(user_id, id).await?;
+
+        if domain.verified_at.is_some() {
+            return Ok(domain);
+        }
+
+        let name = format!("{}.{}", TXT_PREFIX, domain.host);
+        let expected = format!("{}{}", TXT_VALUE, domain.token);
+        let records = resolver.txt(&name).await?;
+
+        if !records.iter().any(|r| r.trim() == expected) {
+            return Err(AppError::UserError(format!(
+                "{} by domain txt record not find",
+                name
+            )));
+        }
+
+        let domain = sqlx::query_as(
+            "
+            UPDATE domains SET verified_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND id = $2 RETURNING *
+            ",
+        )
+        .bind(user_id)
+        .bind(id)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| match &e {
+            sqlx::Error::Database(db) if db.is_unique_violation() => AppError::Conflict(format!(
+                "{} by domain is verified by another user",
+                domain.host
+            )),
+            _ => e.into(),
+        })?;
+
+        Ok(domain)
+    }
+
+    pub async fn set_domain_apps(
+        &self,
+        user_id: i64,
+        id: i64,
+        input: DomainApps,
+    ) -> Result<Domain, AppError> {
+        if let Some(aasa) = &input.apple_app_site_association {
+            validate_app_file("apple-app-site-association", aasa, aasa.is_object())?;
+        }
+        if let Some(links) = &input.asset_links {
+            validate_app_file("assetlinks.json", links, links.is_array())?;
+        }
+
+        let domain = sqlx::query_as(
+            "
+            UPDATE domains SET apple_app_site_association = $3, asset_links = $4
+            WHERE user_id = $1 AND id = $2 RETURNING *
+            ",
+        )
+        .bind(user_id)
+        .bind(id)
+        .bind(input.apple_app_site_association.map(Json))
+        .bind(input.asset_links.map(Json))
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by domain not find", id)))?;
+
+        Ok(domain)
+    }
+
+    // a domain can only be deleted when no url uses it anymore
+    pub async fn delete_domain(&self, user_id: i64, id: i64) -> Result<Domain, AppError> {
+        let domain = sqlx::query_as(
+            "
+            DELETE FROM domains WHERE user_id = $1 AND id = $2 RETURNING *
+            ",
+        )
+        .bind(user_id)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| match &e {
+            sqlx::Error::Database(db) if db.is_foreign_key_violation() => {
+                AppError::Conflict(format!("{} by domain still has urls", id))
+            }
+            _ => e.into(),
+        })?
+        .ok_or_else(|| AppError::NotFound(format!("{} by domain not find", id)))?;
+
+        Ok(domain)
+    }
+
+    pub async fn get_domain(&self, user_id: i64, id: i64) -> Result<Domain, AppError> {
+        let domain = sqlx::query_as(
+            "
+            SELECT * FROM domains WHERE user_id = $1 AND id = $2
+            ",
+        )
+        .bind(user_id)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by domain not find", id)))?;
+
+        Ok(domain)
+    }
+
+    // the verified domain of the user new urls are created on
+    pub async fn find_user_domain(&self, user_id: i64, host: &str) -> Result<Domain, AppError> {
+        let host = self.normalize_host(host)?;
+
+        let domain: Domain = sqlx::query_as(
+            "
+            SELECT * FROM domains WHERE user_id = $1 AND host = $2
+            ",
+        )
+        .bind(user_id)
+        .bind(&host)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by domain not find", host)))?;
+
+        if domain.verified_at.is_none() {
+            return Err(AppError::Forbidden(format!(
+                "{} by domain is not verified",
+                host
+            )));
+        }
+
+        Ok(domain)
+    }
+
+    // the verified domain serving the `Host` of a visit, none for the default host
+    pub async fn find_domain_by_host(&self, host: &str) -> Result<Option<Domain>, AppError> {
+        let domain = sqlx::query_as(
+            "
+            SELECT * FROM domains WHERE host = $1 AND verified_at IS NOT NULL
+            ",
+        )
+        .bind(host)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(domain)
+    }
+
+    // the public short url of the url, on its own domain when it has one
+    pub async fn link(&self, url: &Url) -> Result<String, AppError> {
+        let Some(domain_id) = url.domain_id else {
+            return Ok(self.short_url(&url.short));
+        };
+
+        let host: String = sqlx::query_scalar(
+            "
+            SELECT host FROM domains WHERE id = $1
+            ",
+        )
+        .bind(domain_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(self.link_on(Some(&host), &url.short))
+    }
+
+    pub(crate) fn link_on(&self, host: Option<&str>, short: &str) -> String {
+        match host {
+            Some(host) => format!("https://{}/{}", host, short),
+            None => self.short_url(short),
+        }
+    }
+
+    // lowercase punycode host, ips and the default host can not be registered
+    fn normalize_host(&self, host: &str) -> Result<String, AppError> {
+        let host = host.trim().trim_end_matches('.');
+        let parsed = url::Url::parse(&format!("http://{}/", host))
+            .ok()
+            .filter(|u| u.port().is_none() && u.username().is_empty())
+            .and_then(|u| u.domain().map(|d| d.to_string()))
+            .filter(|d| d.contains('.') && d.len() <= 253)
+            .ok_or_else(|| AppError::UserError(format!("{} by domain is invalid", host)))?;
+
+        let default_host = self
+            .config
+            .server
+            .base_url
+            .as_deref()
+            .and_then(|base| url::Url::parse(base).ok())
+            .and_then(|u| u.host_str().map(|h| h.to_string()));
+        if default_host.as_deref() == Some(parsed.as_str()) {
+            return Err(AppError::UserError(format!(
+                "{} by domain is the default host",
+                host
+            )));
+        }
+
+        Ok(parsed)
+    }
+}
+
+// the apple file is an object and the android file an array
+fn validate_app_file(name: &str, value: &Value, shape: bool) -> Result<(), AppError> {
+    if !shape {
+        return Err(AppError::UserError(format!(
+            "{} by domain app file has the wrong shape",
+            name
+        )));
+    }
+
+    if value.to_string().len() > MAX_APP_FILE {
+        return Err(AppError::UserError(format!(
+            "{} by domain app file must not be larger than {} bytes",
+            name, MAX_APP_FILE
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dns::FakeResolver, CreateUrl, CreateUser, UpdateProfile, UpdateProfileLink, UrlKey,
+    };
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn verify_domain_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let domain = state
+            .create_domain(
+                user.id,
+                CreateDomain {
+                    host: "Go.Acme.com.".to_string(),
+                },
+            )
+            .await?;
+
+        assert_eq!(domain.host, "go.acme.com");
+        assert!(domain.verified_at.is_none());
+
+        let ret = state
+            .create_domain(
+                user.id,
+                CreateDomain {
+                    host: "go.acme.com".to_string(),
+                },
+            )
+            .await;
+        assert!(matches!(ret, Err(AppError::Conflict(_))));
+
+        for host in ["localhost", "127.0.0.1", "go.acme.com:8080", "a b.com"] {
+            let ret = state
+                .create_domain(
+                    user.id,
+                    CreateDomain {
+                        host: host.to_string(),
+                    },
+                )
+                .await;
+            assert!(matches!(ret, Err(AppError::UserError(_))), "{}", host);
+        }
+
+        let resolver = FakeResolver::default();
+        let ret = state
+            .verify_domain_with(user.id, domain.id, &resolver)
+            .await;
+        assert!(matches!(ret, Err(AppError::UserError(_))));
+
+        resolver.insert("_shortener.go.acme.com", "shortener-verify=wrong");
+        resolver.insert(
+            "_shortener.go.acme.com",
+            &format!("shortener-verify={}", domain.token),
+        );
+        let domain = state
+            .verify_domain_with(user.id, domain.id, &resolver)
+            .await?;
+        assert!(domain.verified_at.is_some());
+
+        let ret = state.find_domain_by_host("go.acme.com").await?;
+        assert_eq!(ret.map(|d| d.id), Some(domain.id));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn set_domain_apps_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let domain = state
+            .create_domain(
+                user.id,
+                CreateDomain {
+                    host: "go.acme.com".to_string(),
+                },
+            )
+            .await?;
+
+        let aasa = serde_json::json!({"applinks": {"details": [{"appIDs": ["ABCDE12345.com.acme.app"], "components": [{"/": "/*"}]}]}});
+        let input = DomainApps {
+            apple_app_site_association: Some(aasa.clone()),
+            asset_links: Some(serde_json::json!({"relation": []})),
+        };
+        let ret = state.set_domain_apps(user.id, domain.id, input).await;
+        assert!(matches!(ret, Err(AppError::UserError(_))));
+
+        let input = DomainApps {
+            apple_app_site_association: Some(aasa.clone()),
+            asset_links: None,
+        };
+        state.set_domain_apps(user.id, domain.id, input).await?;
+
+        // files of unverified domains are not served
+        assert!(state.find_domain_by_host("go.acme.com").await?.is_none());
+
+        let resolver = FakeResolver::default();
+        resolver.insert(
+            "_shortener.go.acme.com",
+            &format!("shortener-verify={}", domain.token),
+        );
+        state
+            .verify_domain_with(user.id, domain.id, &resolver)
+            .await?;
+
+        let domain = state
+            .find_domain_by_host("go.acme.com")
+            .await?
+            .expect("domain should exists");
+        assert_eq!(domain.apple_app_site_association.map(|v| v.0), Some(aasa));
+        assert!(domain.asset_links.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shorten_on_domain_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let domain = state
+            .create_domain(
+                user.id,
+                CreateDomain {
+                    host: "go.acme.com".to_string(),
+                },
+            )
+            .await?;
+
+        let input = CreateUrl {
+            alias: Some("launch".to_string()),
+            domain: Some("go.acme.com".to_string()),
+            ..CreateUrl::new("https://www.acme.com/launch")
+        };
+
+        // only verified domains take urls
+        let ret = state.shorten(user.id, input.clone()).await;
+        assert!(matches!(ret, Err(AppError::Forbidden(_))));
+
+        let resolver = FakeResolver::default();
+        resolver.insert(
+            "_shortener.go.acme.com",
+            &format!("shortener-verify={}", domain.token),
+        );
+        state
+            .verify_domain_with(user.id, domain.id, &resolver)
+            .await?;
+
+        let ret = state.shorten(user.id, input).await?;
+        assert_eq!(ret.output, "launch");
+
+        // the owner may use the same short code on the default host too
+        let input = CreateUrl {
+            alias: Some("launch".to_string()),
+            ..CreateUrl::new("https://www.acme.com/default")
+        };
+        state.shorten(user.id, input).await?;
+
+        let on_domain = UrlKey::new("launch", Some("GO.acme.com"));
+        let url = state.get_url(user.id, on_domain).await?;
+        assert_eq!(url.url, "https://www.acme.com/launch");
+        let url = state.get_url(user.id, "launch").await?;
+        assert_eq!(url.url, "https://www.acme.com/default");
+        let ret = state
+            .get_url(user.id, UrlKey::new("launch", Some("other.acme.com")))
+            .await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        // both are different links of the profile
+        let link = |domain: Option<&str>| UpdateProfileLink {
+            short: "launch".to_string(),
+            domain: domain.map(|d| d.to_string()),
+            title: None,
+            icon: None,
+        };
+        let profile = |links| UpdateProfile {
+            username: "teammeng".to_string(),
+            title: None,
+            bio: None,
+            links,
+        };
+        let page = state
+            .update_profile(
+                user.id,
+                profile(vec![link(None), link(Some("go.acme.com"))]),
+            )
+            .await?;
+        assert_eq!(page.links.len(), 2);
+        assert_eq!(page.links[1].href, "https://go.acme.com/launch");
+        let ret = state
+            .update_profile(
+                user.id,
+                profile(vec![link(Some("go.acme.com")), link(Some("GO.acme.com"))]),
+            )
+            .await;
+        assert!(matches!(ret, Err(AppError::UserError(_))));
+        state.delete_profile(user.id).await?;
+
+        state.delete_url(user.id, "launch").await?;
+
+        // the same short code lives on the default host of another user
+        let other = state
+            .create_user(CreateUser {
+                name: "TeamAlice".to_string(),
+                email: "Alice@123.com".to_string(),
+                password: "123456".to_string(),
+            })
+            .await?;
+        let input = CreateUrl {
+            alias: Some("launch".to_string()),
+            ..CreateUrl::new("https://www.qq.com/")
+        };
+        state.shorten(other.id, input).await?;
+
+        let url = state
+            .resolve_url_on(Some(domain.id), "launch", None)
+            .await?;
+        assert_eq!(url.url, "https://www.acme.com/launch");
+        assert_eq!(state.link(&url).await?, "https://go.acme.com/launch");
+
+        let url = state.resolve_url("launch", None).await?;
+        assert_eq!(url.url, "https://www.qq.com/");
+
+        let ret = state.delete_domain(user.id, domain.id).await;
+        assert!(matches!(ret, Err(AppError::Conflict(_))));
+
+        state.delete_url(user.id, on_domain).await?;
+        state.delete_domain(user.id, domain.id).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+use crate::{AppError, AppState, Url};
+use async_stream::try_stream;
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures::{Stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+const CSV_HEADER: [&str; 11] = [
+    "id",
+    "short",
+    "domain",
+    "url",
+    "title",
+    "redirect_status",
+    "activate_at",
+    "expires_at",
+    "max_clicks",
+    "created_at",
+    "updated_at",
+];
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+    #[default]
+    Json,
+    Ndjson,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+    // include the click count of each url
+    #[serde(default)]
+    pub clicks: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportRow {
+    pub id: i64,
+    pub short: String,
+    // host of the custom domain, empty for the default host
+    pub domain: Option<String>,
+    pub url: String,
+    pub title: Option<String>,
+    pub redirect_status: i16,
+    pub activate_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_clicks: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clicks: Option<i64>,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Json => "application/json",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+            Self::Ndjson => "ndjson",
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct UrlWithHost {
+    #[sqlx(flatten)]
+    url: Url,
+    host: Option<String>,
+}
+
+impl ExportRow {
+    fn new(UrlWithHost { url, host }: UrlWithHost, clicks: bool) -> Self {
+        Self {
+            id: url.id,
+            short: url.short,
+            domain: host,
+            url: url.url,
+            title: url.title,
+            redirect_status: url.redirect_status,
+            activate_at: url.activate_at,
+            expires_at: url.expires_at,
+            max_clicks: url.max_clicks,
+            created_at: url.created_at,
+            updated_at: url.updated_at,
+            clicks: clicks.then_some(url.clicks),
+        }
+    }
+}
+
+impl AppState {
+    // stream the urls of the user row by row, nothing is collected in memory
+    pub fn export_urls(
+        &self,
+        user_id: i64,
+        query: ExportQuery,
+    ) -> impl Stream<Item = Result<Bytes, AppError>> + Send + 'static {
+        let pool = self.pool.clone();
+
+        try_stream! {
+            let mut rows = sqlx::query_as::<_, UrlWithHost>(
+                "
+                SELECT urls.*, domains.host FROM urls LEFT JOIN domains ON domains.id = urls.domain_id
+                WHERE urls.user_id = $1 ORDER BY urls.id
+                ",
+            )
+            .bind(user_id)
+            .fetch(&pool);
+
+            match query.format {
+                ExportFormat::Csv => {
+                    let mut header = CSV_HEADER.to_vec();
+                    if query.clicks {
+                        header.push("clicks");
+                    }
+                    yield encode_csv(&header)?;
+                }
+                ExportFormat::Json => yield Bytes::from_static(b"["),
+                ExportFormat::Ndjson => {}
+            }
+
+            let mut first = true;
+            while let Some(url) = rows.try_next().await? {
+                let row = ExportRow::new(url, query.clicks);
+                let data = match query.format {
+                    ExportFormat::Csv => encode_csv(&row)?,
+                    ExportFormat::Json => {
+                        let mut data = if first { vec![] } else { b",".to_vec() };
+                        data.extend(encode_json(&row)?);
+                        Bytes::from(data)
+                    }
+                    ExportFormat::Ndjson => {
+                        let mut data = encode_json(&row)?;
+                        data.push(b'\n');
+                        Bytes::from(data)
+                    }
+                };
+                first = false;
+                yield data;
+            }
+
+            if query.format == ExportFormat::Json {
+                yield Bytes::from_static(b"]");
+            }
+        }
+    }
+}
+
+fn encode_csv(record: impl Serialize) -> Result<Bytes, AppError> {
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(vec![]);
+    wtr.serialize(record)?;
+    let data = wtr.into_inner().map_err(|e| e.into_error())?;
+
+    Ok(Bytes::from(data))
+}
+
+fn encode_json(row: &ExportRow) -> Result<Vec<u8>, AppError> {
+    Ok(serde_json::to_vec(row)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dns::FakeResolver, CreateDomain, CreateUrl};
+    use anyhow::Result;
+
+    async fn export(state: &AppState, user_id: i64, query: ExportQuery) -> Result<String> {
+        let data: Vec<Bytes> = state.export_urls(user_id, query).try_collect().await?;
+
+        Ok(String::from_utf8(data.concat())?)
+    }
+
+    #[tokio::test]
+    async fn export_urls_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let query = ExportQuery {
+            format: ExportFormat::Json,
+            clicks: false,
+        };
+        assert_eq!(export(&state, user.id, query).await?, "[]");
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.title = Some("baidu, search".to_string());
+        let id = state.shorten(user.id, input).await?;
+        state.resolve_url(&id.output, None).await?;
+        state
+            .shorten(user.id, CreateUrl::new("https://www.360.com/"))
+            .await?;
+
+        // the same short code on a custom domain
+        let domain = state
+            .create_domain(
+                user.id,
+                CreateDomain {
+                    host: "go.acme.com".to_string(),
+                },
+            )
+            .await?;
+        let resolver = FakeResolver::default();
+        resolver.insert(
+            "_shortener.go.acme.com",
+            &format!("shortener-verify={}", domain.token),
+        );
+        state
+            .verify_domain_with(user.id, domain.id, &resolver)
+            .await?;
+        let input = CreateUrl {
+            alias: Some(id.output.clone()),
+            domain: Some("go.acme.com".to_string()),
+            ..CreateUrl::new("https://www.acme.com/")
+        };
+        state.shorten(user.id, input).await?;
+
+        let query = ExportQuery {
+            format: ExportFormat::Csv,
+            clicks: true,
+        };
+        let data = export(&state, user.id, query).await?;
+        let mut rdr = csv::Reader::from_reader(data.as_bytes());
+        let rows: Vec<ExportRow> = rdr.deserialize().collect::<Result<_, _>>()?;
+
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].short, id.output);
+        assert_eq!(rows[0].domain, None);
+        assert_eq!(rows[0].title.as_deref(), Some("baidu, search"));
+        assert_eq!(rows[0].clicks, Some(1));
+        assert_eq!(rows[2].short, id.output);
+        assert_eq!(rows[2].domain.as_deref(), Some("go.acme.com"));
+
+        let query = ExportQuery {
+            format: ExportFormat::Json,
+            clicks: false,
+        };
+        let data = export(&state, user.id, query).await?;
+        let rows: Vec<ExportRow> = serde_json::from_str(&data)?;
+
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2].domain.as_deref(), Some("go.acme.com"));
+        assert_eq!(rows[1].url, "https://www.360.com/");
+        assert!(rows[1].clicks.is_none());
+
+        let query = ExportQuery {
+            format: ExportFormat::Ndjson,
+            clicks: true,
+        };
+        let data = export(&state, user.id, query).await?;
+        let rows = data
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<ExportRow>, _>>()?;
+
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[1].clicks, Some(0));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{AppError, AppState, Url};
+use chrono::Utc;
+use futures::future::join_all;
+use reqwest::StatusCode;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// how often the checker looks for due destinations, each url is still checked once per interval
+const HEALTH_TICK: Duration = Duration::from_secs(300);
+
+// check the destinations of the enabled urls so dead links show up before visitors find them
+pub fn spawn_health_check(state: AppState, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval.min(HEALTH_TICK));
+
+        loop {
+            ticker.tick().await;
+            match state.check_urls().await {
+                Ok(checked) if checked > 0 => info!("health checked {} urls", checked),
+                Ok(_) => {}
+                Err(e) => warn!("health check urls failed: {}", e),
+            }
+        }
+    });
+}
+
+impl AppState {
+    // check every enabled url not checked within the interval, returns how many were checked
+    pub async fn check_urls(&self) -> Result<usize, AppError> {
+        let due = Utc::now() - chrono::Duration::seconds(self.config.health.interval as i64);
+        let mut checked = 0;
+
+        loop {
+            let urls: Vec<Url> = sqlx::query_as(
+                "
+                SELECT * FROM urls
+                WHERE disabled_at IS NULL AND (health_checked_at IS NULL OR health_checked_at < $1)
+                ORDER BY health_checked_at NULLS FIRST, id LIMIT $2
+                ",
+            )
+            .bind(due)
+            .bind(self.config.health.batch)
+            .fetch_all(&self.pool)
+            .await?;
+
+            if urls.is_empty() {
+                return Ok(checked);
+            }
+
+            // a failed url is logged and left due, the others are still checked
+            let rets = join_all(urls.iter().map(|url| self.check_url(url))).await;
+            let mut done = 0;
+            for (url, ret) in urls.iter().zip(rets) {
+                match ret {
+                    Ok(Some(_)) => {
+                        checked += 1;
+                        done += 1;
+                    }
+                    // deleted meanwhile
+                    Ok(None) => done += 1,
+                    Err(e) => warn!("health check url {} failed: {}", url.id, e),
+                }
+            }
+
+            // the batch would be selected again, wait for the next tick
+            if done == 0 {
+                return Ok(checked);
+            }
+        }
+    }
+
+    // record the status and latency of the destination, a success clears the broken mark, none
+    // when the url was deleted meanwhile
+    pub async fn check_url(&self, url: &Url) -> Result<Option<Url>, AppError> {
+        let start = Instant::now();
+        let ret = self.previewer.probe(&url.url).await;
+        let latency = start.elapsed().as_millis().min(i32::MAX as u128) as i32;
+
+        let (status, latency, alive) = match ret {
+            Ok(status) => (
+                Some(status.as_u16() as i16),
+                Some(latency),
+                is_alive(status),
+            ),
+            Err(_) => (None, None, false),
+        };
+
+        let url = sqlx::query_as(
+            "
+            UPDATE urls SET health_status = $2, health_latency_ms = $3, health_checked_at = CURRENT_TIMESTAMP,
+                health_failures = CASE WHEN $4 THEN 0 ELSE health_failures + 1 END,
+                broken_at = CASE
+                    WHEN $4 THEN NULL
+                    WHEN health_failures + 1 >= $5 THEN COALESCE(broken_at, CURRENT_TIMESTAMP)
+                    ELSE broken_at
+                END
+            WHERE id = $1 RETURNING *
+            ",
+        )
+        .bind(url.id)
+        .bind(status)
+        .bind(latency)
+        .bind(alive)
+        .bind(self.config.health.failures)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(url)
+    }
+
+    pub async fn list_broken_urls(&self, user_id: i64) -> Result<Vec<Url>, AppError> {
+        let urls = sqlx::query_as(
+            "
+            SELECT * FROM urls WHERE user_id = $1 AND broken_at IS NOT NULL
+            ORDER BY broken_at DESC, id
+            ",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(urls)
+    }
+}
+
+// auth walls and rate limits still mean the page exists
+fn is_alive(status: StatusCode) -> bool {
+    !(status.is_server_error() || status == StatusCode::NOT_FOUND || status == StatusCode::GONE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateUrl;
+    use anyhow::Result;
+    use axum::{
+        http::StatusCode,
+        routing::{get, head},
+        Router,
+    };
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+    use tokio::net::TcpListener;
+
+    // a local stand-in whose `/flaky` page can be switched off
+    async fn serve(up: Arc<AtomicBool>) -> Result<SocketAddr> {
+        let app = Router::new()
+            .route("/ok", get(|| async { "ok" }))
+            .route(
+                "/no-head",
+                head(|| async { StatusCode::METHOD_NOT_ALLOWED }).get(|| async { "ok" }),
+            )
+            .route(
+                "/flaky",
+                get(move || async move {
+                    if up.load(Ordering::SeqCst) {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    }
+                }),
+            );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn check_urls_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let up = Arc::new(AtomicBool::new(false));
+        let addr = serve(up.clone()).await?;
+
+        let mut shorts = vec![];
+        for path in ["ok", "no-head", "flaky"] {
+            let input = CreateUrl::new(format!("http://{}/{}", addr, path));
+            shorts.push(state.shorten(user.id, input).await?.output);
+        }
+
+        assert_eq!(state.check_urls().await?, 3);
+        // everything was checked within the interval
+        assert_eq!(state.check_urls().await?, 0);
+
+        let url = state.get_url(user.id, &shorts[1]).await?;
+
+        assert_eq!(url.health_status, Some(200));
+        assert!(url.health_latency_ms.is_some());
+
+        let mut url = state.get_url(user.id, &shorts[2]).await?;
+
+        assert_eq!(url.health_status, Some(503));
+        assert_eq!(url.health_failures, 1);
+        assert!(url.broken_at.is_none());
+
+        for _ in 0..2 {
+            url = state.check_url(&url).await?.expect("url should exists");
+        }
+
+        assert_eq!(url.health_failures, 3);
+        assert!(url.broken_at.is_some());
+
+        let broken = state.list_broken_urls(user.id).await?;
+
+        assert_eq!(broken.len(), 1);
+        assert_eq!(broken[0].short, shorts[2]);
+
+        up.store(true, Ordering::SeqCst);
+        let url = state.check_url(&url).await?.expect("url should exists");
+
+        assert_eq!(url.health_failures, 0);
+        assert!(url.broken_at.is_none());
+        assert!(state.list_broken_urls(user.id).await?.is_empty());
+
+        // deleted between the select and the update
+        state.delete_url(user.id, &url.short).await?;
+        assert!(state.check_url(&url).await?.is_none());
+
+        Ok(())
+    }
+}
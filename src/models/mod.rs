@@ -1,5 +1,22 @@
+mod block;
+mod bulk;
+mod click;
+mod domain;
+mod export;
+mod health;
+mod preview;
+mod profile;
+mod tag;
 mod url;
 mod user;
 
+pub use block::*;
+pub use bulk::*;
+pub use click::*;
+pub use domain::*;
+pub use export::*;
+pub use health::*;
+pub use profile::*;
+pub use tag::*;
 pub use url::*;
 pub use user::*;
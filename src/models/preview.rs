@@ -0,0 +1,181 @@
+use crate::{AppError, AppState, Preview, Url, UrlKey};
+use chrono::{Duration, Utc};
+use sqlx::types::Json;
+use tracing::warn;
+
+impl AppState {
+    // fetch the previews of new destinations in the background
+    pub fn queue_previews(&self, ids: Vec<i64>) {
+        if !self.config.preview.enabled {
+            return;
+        }
+
+        for id in ids {
+            let state = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = state.refresh_preview(id, false).await {
+                    warn!("fetch preview of url {} failed: {}", id, e);
+                }
+            });
+        }
+    }
+
+    pub async fn refresh_url_preview<'a>(
+        &self,
+        user_id: i64,
+        key: impl Into<UrlKey<'a>>,
+    ) -> Result<Url, AppError> {
+        let url = self.get_url(user_id, key).await?;
+
+        self.refresh_preview(url.id, true).await
+    }
+
+    // store the preview of the destination, a fresh one is kept or copied from another url with
+    // the same destination unless `force`
+    pub async fn refresh_preview(&self, id: i64, force: bool) -> Result<Url, AppError> {
+        let url: Url = sqlx::query_as(
+            "
+            SELECT * FROM urls WHERE id = $1
+            ",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by url not find", id)))?;
+
+        let fresh = Utc::now() - Duration::seconds(self.config.preview.ttl as i64);
+        if !force && url.preview_fetched_at.is_some_and(|t| t > fresh) {
+            return Ok(url);
+        }
+
+        let cached: Option<(Json<Preview>,)> = if force {
+            None
+        } else {
+            sqlx::query_as(
+                "
+                SELECT preview FROM urls
+                WHERE url = $1 AND preview IS NOT NULL AND preview_fetched_at > $2
+                ORDER BY preview_fetched_at DESC LIMIT 1
+                ",
+            )
+            .bind(&url.url)
+            .bind(fresh)
+            .fetch_optional(&self.pool)
+            .await?
+        };
+
+        // a failed fetch is recorded too, so it is not retried before the ttl runs out
+        let (preview, err) = match cached {
+            Some((preview,)) => (Some(preview.0), None),
+            None => match self.previewer.fetch(&url.url).await {
+                Ok(preview) => (Some(preview), None),
+                Err(e) => (None, Some(e)),
+            },
+        };
+
+        // the destination may have changed while the page was fetched
+        let url = sqlx::query_as(
+            "
+            UPDATE urls SET preview = $3, preview_fetched_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND url = $2 RETURNING *
+            ",
+        )
+        .bind(id)
+        .bind(&url.url)
+        .bind(preview.map(Json))
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| {
+            AppError::Conflict(format!("{} by url changed while fetching the preview", id))
+        })?;
+
+        match err {
+            Some(e) => Err(e),
+            None => Ok(url),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CreateUrl, UpdateUrl};
+    use anyhow::Result;
+    use axum::{response::Html, routing::get, Router};
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tokio::net::TcpListener;
+
+    // a local stand-in page counting its visits
+    async fn serve(hits: Arc<AtomicUsize>) -> Result<SocketAddr> {
+        let app = Router::new().route(
+            "/",
+            get(move || async move {
+                hits.fetch_add(1, Ordering::SeqCst);
+                Html(r#"<title>Stand-in</title><meta name="description" content="A page">"#)
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn refresh_preview_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let addr = serve(hits.clone()).await?;
+        let target = format!("http://{}/", addr);
+
+        let id = state.shorten(user.id, CreateUrl::new(&target)).await?;
+        let url = state.get_url(user.id, &id.output).await?;
+
+        assert!(url.preview.is_none());
+
+        let url = state.refresh_preview(url.id, false).await?;
+        let preview = url.preview.expect("preview should exists");
+
+        assert_eq!(preview.title.as_deref(), Some("Stand-in"));
+        assert_eq!(preview.description.as_deref(), Some("A page"));
+        assert!(url.preview_fetched_at.is_some());
+
+        // the same destination reuses the fresh preview
+        let mut input = CreateUrl::new(&target);
+        input.title = Some("other".to_string());
+        let other = state.shorten(user.id, input).await?;
+        let other = state.get_url(user.id, &other.output).await?;
+        let other = state.refresh_preview(other.id, false).await?;
+
+        assert_eq!(other.preview.map(|p| p.0), Some(preview.0));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        state.refresh_url_preview(user.id, &id.output).await?;
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        // a new destination drops the old preview
+        let input = UpdateUrl {
+            url: Some(format!("{}other", target)),
+            ..Default::default()
+        };
+        let url = state.update_url(user.id, &id.output, input).await?;
+
+        assert!(url.preview.is_none());
+        assert!(url.preview_fetched_at.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,440 @@
+use crate::{normalize_url, AppError, AppState, UrlKey};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashSet;
+
+const USERNAME_MIN_LEN: usize = 3;
+const USERNAME_MAX_LEN: usize = 32;
+const MAX_TITLE: usize = 128;
+const MAX_BIO: usize = 1024;
+const MAX_LINKS: usize = 100;
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub user_id: i64,
+    pub username: String,
+    pub title: Option<String>,
+    pub bio: Option<String>,
+    // visits of the public page, clicks on its links are counted by the urls
+    pub views: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// a link as shown on the page, `href` is the short url so the click is recorded
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfileLink {
+    pub short: String,
+    pub href: String,
+    pub title: String,
+    pub icon: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfilePage {
+    #[serde(flatten)]
+    pub profile: Profile,
+    pub links: Vec<ProfileLink>,
+}
+
+// replaces the profile and all of its links
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateProfile {
+    pub username: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub bio: Option<String>,
+    // shown in this order
+    #[serde(default)]
+    pub links: Vec<UpdateProfileLink>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateProfileLink {
+    // short code of one of the urls of the user, and the host when the url is on a custom domain
+    pub short: String,
+    #[serde(default)]
+    pub domain: Option<String>,
+    // the title of the url when missing, the short url for a protected one
+    #[serde(default)]
+    pub title: Option<String>,
+    // image url shown before the title
+    #[serde(default)]
+    pub icon: Option<String>,
+}
+
+#[derive(FromRow)]
+struct LinkRow {
+    short: String,
+    host: Option<String>,
+    title: Option<String>,
+    icon: Option<String>,
+}
+
+impl AppState {
+    pub async fn get_profile(&self, user_id: i64) -> Result<ProfilePage, AppError> {
+        let profile: Profile = sqlx::query_as(
+            "
+            SELECT * FROM profiles WHERE user_id = $1
+            ",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by profile not find", user_id)))?;
+
+        let links = self.profile_links(user_id, false).await?;
+
+        Ok(ProfilePage { profile, links })
+    }
+
+    pub async fn update_profile(
+        &self,
+        user_id: i64,
+        input: UpdateProfile,
+    ) -> Result<ProfilePage, AppError> {
+        let username = validate_username(&input.username)?;
+        validate_text("title", input.title.as_deref(), MAX_TITLE)?;
+        validate_text("bio", input.bio.as_deref(), MAX_BIO)?;
+
+        if input.links.len() > MAX_LINKS {
+            return Err(AppError::UserError(format!(
+                "{} by profile links must not be more than {}",
+                input.links.len(),
+                MAX_LINKS
+            )));
+        }
+
+        // the same short code may be taken on every host of the user
+        let mut keys = HashSet::new();
+        let mut icons = Vec::with_capacity(input.links.len());
+        for link in &input.links {
+            let domain = link
+                .domain
+                .as_deref()
+                .map(|d| d.trim_end_matches('.').to_ascii_lowercase());
+            if !keys.insert((link.short.as_str(), domain)) {
+                let key = UrlKey::new(&link.short, link.domain.as_deref());
+                return Err(AppError::UserError(format!(
+                    "{} by profile link is duplicated",
+                    key
+                )));
+            }
+            validate_text("link title", link.title.as_deref(), MAX_TITLE)?;
+            let icon = link
+                .icon
+                .as_deref()
+                .map(|icon| normalize_url(&self.config.validation, icon, false))
+                .transpose()?;
+            icons.push(icon);
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            "
+            INSERT INTO profiles (user_id, username, title, bio) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id) DO UPDATE
+            SET username = $2, title = $3, bio = $4, updated_at = CURRENT_TIMESTAMP
+            ",
+        )
+        .bind(user_id)
+        .bind(&username)
+        .bind(&input.title)
+        .bind(&input.bio)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| match &e {
+            sqlx::Error::Database(db) if db.is_unique_violation() => {
+                AppError::Conflict(format!("{} by username already exists", username))
+            }
+            _ => e.into(),
+        })?;
+
+        sqlx::query("DELETE FROM profile_links WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for (position, (link, icon)) in input.links.iter().zip(icons).enumerate() {
+            let key = UrlKey::new(&link.short, link.domain.as_deref());
+            let domain_id = self.key_domain_id(user_id, key).await?;
+
+            let inserted = sqlx::query(
+                "
+                INSERT INTO profile_links (user_id, url_id, position, title, icon)
+                SELECT $1, id, $3, $4, $5 FROM urls
+                WHERE user_id = $1 AND short = $2 AND domain_id IS NOT DISTINCT FROM $6
+                ",
+            )
+            .bind(user_id)
+            .bind(&link.short)
+            .bind(position as i32)
+            .bind(&link.title)
+            .bind(icon)
+            .bind(domain_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+            if inserted == 0 {
+                return Err(AppError::NotFound(format!("{} by url not find", key)));
+            }
+        }
+
+        tx.commit().await?;
+
+        self.get_profile(user_id).await
+    }
+
+    pub async fn delete_profile(&self, user_id: i64) -> Result<Profile, AppError> {
+        let profile = sqlx::query_as(
+            "
+            DELETE FROM profiles WHERE user_id = $1 RETURNING *
+            ",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by profile not find", user_id)))?;
+
+        Ok(profile)
+    }
+
+    // the public page, counts the view and only lists links visitors can open
+    pub async fn visit_profile(&self, username: &str) -> Result<ProfilePage, AppError> {
+        let profile: Profile = sqlx::query_as(
+            "
+            UPDATE profiles SET views = views + 1 WHERE username = $1 RETURNING *
+            ",
+        )
+        .bind(username.to_ascii_lowercase())
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by profile not find", username)))?;
+
+        let links = self.profile_links(profile.user_id, true).await?;
+
+        Ok(ProfilePage { profile, links })
+    }
+
+    async fn profile_links(
+        &self,
+        user_id: i64,
+        active: bool,
+    ) -> Result<Vec<ProfileLink>, AppError> {
+        let rows: Vec<LinkRow> = sqlx::query_as(
+            "
+            SELECT urls.short, domains.host, profile_links.icon,
+                CASE WHEN urls.password_hash IS NULL
+                    THEN COALESCE(profile_links.title, urls.title, urls.url)
+                    ELSE profile_links.title
+                END AS title
+            FROM profile_links
+                JOIN urls ON urls.id = profile_links.url_id
+                LEFT JOIN domains ON domains.id = urls.domain_id
+            WHERE profile_links.user_id = $1 AND (NOT $2 OR (
+                urls.disabled_at IS NULL
+                AND (urls.activate_at IS NULL OR urls.activate_at <= CURRENT_TIMESTAMP)
+                AND (urls.expires_at IS NULL OR urls.expires_at > CURRENT_TIMESTAMP)
+                AND (urls.max_clicks IS NULL OR urls.clicks < urls.max_clicks)
+                AND (urls.remaining_uses IS NULL OR urls.remaining_uses > 0)
+            ))
+            ORDER BY profile_links.position
+            ",
+        )
+        .bind(user_id)
+        .bind(active)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let links = rows
+            .into_iter()
+            .map(|row| {
+                let href = self.link_on(row.host.as_deref(), &row.short);
+                // the destination and title of a protected url stay behind its password
+                ProfileLink {
+                    title: row.title.unwrap_or_else(|| href.clone()),
+                    href,
+                    short: row.short,
+                    icon: row.icon,
+                }
+            })
+            .collect();
+
+        Ok(links)
+    }
+}
+
+// lowercase letters, digits and '_'
+fn validate_username(username: &str) -> Result<String, AppError> {
+    let username = username.to_ascii_lowercase();
+
+    if username.len() < USERNAME_MIN_LEN || username.len() > USERNAME_MAX_LEN {
+        return Err(AppError::UserError(format!(
+            "{} by username length must be between {} and {}",
+            username, USERNAME_MIN_LEN, USERNAME_MAX_LEN
+        )));
+    }
+
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(AppError::UserError(format!(
+            "{} by username only allows letters, digits and '_'",
+            username
+        )));
+    }
+
+    Ok(username)
+}
+
+fn validate_text(name: &str, value: Option<&str>, max: usize) -> Result<(), AppError> {
+    if value.is_some_and(|v| v.chars().count() > max) {
+        return Err(AppError::UserError(format!(
+            "{} by profile must not be longer than {}",
+            name, max
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateUrl;
+    use anyhow::Result;
+    use chrono::Duration;
+
+    #[tokio::test]
+    async fn update_profile_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        assert!(matches!(
+            state.get_profile(user.id).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.title = Some("Baidu".to_string());
+        let baidu = state.shorten(user.id, input).await?;
+        let qq = state
+            .shorten(user.id, CreateUrl::new("https://www.qq.com/"))
+            .await?;
+        let mut input = CreateUrl::new("https://www.qq.com/sale");
+        input.expires_at = Some(Utc::now() + Duration::milliseconds(500));
+        let sale = state.shorten(user.id, input).await?;
+
+        let link = |short: &str, title: Option<&str>| UpdateProfileLink {
+            short: short.to_string(),
+            domain: None,
+            title: title.map(|t| t.to_string()),
+            icon: None,
+        };
+        let input = UpdateProfile {
+            username: "TeamMeng".to_string(),
+            title: Some("Team Meng".to_string()),
+            bio: None,
+            links: vec![
+                link(&qq.output, Some("QQ")),
+                link(&baidu.output, None),
+                link(&sale.output, None),
+            ],
+        };
+        let page = state.update_profile(user.id, input).await?;
+
+        assert_eq!(page.profile.username, "teammeng");
+        let titles: Vec<_> = page.links.iter().map(|l| l.title.as_str()).collect();
+        assert_eq!(titles, ["QQ", "Baidu", "https://www.qq.com/sale"]);
+        assert_eq!(page.links[0].href, state.short_url(&qq.output));
+
+        // expired links leave the public page
+        tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+        let page = state.visit_profile("TEAMMENG").await?;
+
+        assert_eq!(page.profile.views, 1);
+        assert_eq!(page.links.len(), 2);
+
+        let input = UpdateProfile {
+            username: "teammeng".to_string(),
+            title: None,
+            bio: None,
+            links: vec![link("nothing", None)],
+        };
+        assert!(matches!(
+            state.update_profile(user.id, input).await,
+            Err(AppError::NotFound(_))
+        ));
+        // the failed update changed nothing
+        assert_eq!(state.get_profile(user.id).await?.links.len(), 3);
+
+        let input = UpdateProfile {
+            username: "no way".to_string(),
+            title: None,
+            bio: None,
+            links: vec![],
+        };
+        assert!(matches!(
+            state.update_profile(user.id, input).await,
+            Err(AppError::UserError(_))
+        ));
+
+        state.delete_url(user.id, &qq.output).await?;
+        assert_eq!(state.get_profile(user.id).await?.links.len(), 2);
+
+        state.delete_profile(user.id).await?;
+        assert!(matches!(
+            state.visit_profile("teammeng").await,
+            Err(AppError::NotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn profile_should_hide_protected_destination() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let mut input = CreateUrl::new("https://www.qq.com/secret-plan");
+        input.title = Some("Secret Plan".to_string());
+        input.password = Some("secret".to_string());
+        let secret = state.shorten(user.id, input).await?;
+
+        let input = UpdateProfile {
+            username: "teammeng".to_string(),
+            title: None,
+            bio: None,
+            links: vec![UpdateProfileLink {
+                short: secret.output.clone(),
+                domain: None,
+                title: None,
+                icon: None,
+            }],
+        };
+        state.update_profile(user.id, input).await?;
+
+        let page = state.visit_profile("teammeng").await?;
+        let body = serde_json::to_string(&page)?;
+
+        assert_eq!(page.links[0].title, state.short_url(&secret.output));
+        assert!(!body.contains("secret-plan"));
+        assert!(!body.contains("Secret Plan"));
+
+        Ok(())
+    }
+}
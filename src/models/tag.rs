@@ -0,0 +1,362 @@
+use crate::{AppError, AppState, UrlKey};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+const MAX_TAG_NAME: usize = 64;
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Tag {
+    pub id: i64,
+    pub user_id: i64,
+    pub kind: TagKind,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// folders work like tags, they are only listed and filtered apart
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum TagKind {
+    #[default]
+    Tag,
+    Folder,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateTag {
+    #[serde(default)]
+    pub kind: TagKind,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RenameTag {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ListTags {
+    pub kind: Option<TagKind>,
+}
+
+// replaces the tags and folders of a url
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AssignTags {
+    pub tags: Vec<i64>,
+}
+
+impl AppState {
+    pub async fn list_tags(&self, user_id: i64, input: ListTags) -> Result<Vec<Tag>, AppError> {
+        let tags = sqlx::query_as(
+            "
+            SELECT * FROM tags WHERE user_id = $1 AND ($2::varchar IS NULL OR kind = $2)
+            ORDER BY kind, name
+            ",
+        )
+        .bind(user_id)
+        .bind(input.kind)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tags)
+    }
+
+    pub async fn create_tag(&self, user_id: i64, input: CreateTag) -> Result<Tag, AppError> {
+        let name = validate_tag_name(&input.name)?;
+
+        let tag = sqlx::query_as(
+            "
+            INSERT INTO tags (user_id, kind, name) VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, kind, name) DO NOTHING RETURNING *
+            ",
+        )
+        .bind(user_id)
+        .bind(input.kind)
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::Conflict(format!("{} by tag already exists", name)))?;
+
+        Ok(tag)
+    }
+
+    pub async fn rename_tag(
+        &self,
+        user_id: i64,
+        id: i64,
+        input: RenameTag,
+    ) -> Result<Tag, AppError> {
+        let name = validate_tag_name(&input.name)?;
+
+        let tag = sqlx::query_as(
+            "
+            UPDATE tags SET name = $3 WHERE user_id = $1 AND id = $2 RETURNING *
+            ",
+        )
+        .bind(user_id)
+        .bind(id)
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| match &e {
+            sqlx::Error::Database(db) if db.is_unique_violation() => {
+                AppError::Conflict(format!("{} by tag already exists", name))
+            }
+            _ => e.into(),
+        })?
+        .ok_or_else(|| AppError::NotFound(format!("{} by tag not find", id)))?;
+
+        Ok(tag)
+    }
+
+    // the urls keep existing, only the assignments are removed
+    pub async fn delete_tag(&self, user_id: i64, id: i64) -> Result<Tag, AppError> {
+        let tag = sqlx::query_as(
+            "
+            DELETE FROM tags WHERE user_id = $1 AND id = $2 RETURNING *
+            ",
+        )
+        .bind(user_id)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by tag not find", id)))?;
+
+        Ok(tag)
+    }
+
+    pub async fn get_url_tags<'a>(
+        &self,
+        user_id: i64,
+        key: impl Into<UrlKey<'a>>,
+    ) -> Result<Vec<Tag>, AppError> {
+        let url = self.get_url(user_id, key).await?;
+
+        let tags = sqlx::query_as(
+            "
+            SELECT tags.* FROM tags JOIN url_tags ON url_tags.tag_id = tags.id
+            WHERE url_tags.url_id = $1 ORDER BY tags.kind, tags.name
+            ",
+        )
+        .bind(url.id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tags)
+    }
+
+    pub async fn set_url_tags<'a>(
+        &self,
+        user_id: i64,
+        key: impl Into<UrlKey<'a>>,
+        input: AssignTags,
+    ) -> Result<Vec<Tag>, AppError> {
+        let key = key.into();
+        let url = self.get_url(user_id, key).await?;
+
+        let mut ids = input.tags;
+        ids.sort_unstable();
+        ids.dedup();
+
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM url_tags WHERE url_id = $1")
+            .bind(url.id)
+            .execute(&mut *tx)
+            .await?;
+
+        // tags of other users are skipped, so they show up as missing below
+        let assigned = sqlx::query(
+            "
+            INSERT INTO url_tags (url_id, tag_id)
+            SELECT $1, id FROM tags WHERE user_id = $2 AND id = ANY($3)
+            ",
+        )
+        .bind(url.id)
+        .bind(user_id)
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if assigned != ids.len() as u64 {
+            return Err(AppError::NotFound(format!("{:?} by tags not find", ids)));
+        }
+
+        tx.commit().await?;
+
+        self.get_url_tags(user_id, key).await
+    }
+}
+
+fn validate_tag_name(name: &str) -> Result<&str, AppError> {
+    let name = name.trim();
+
+    if name.is_empty() || name.chars().count() > MAX_TAG_NAME {
+        return Err(AppError::UserError(format!(
+            "{} by tag name must be 1 to {} characters",
+            name, MAX_TAG_NAME
+        )));
+    }
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CreateUrl, ListUrls};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn tag_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let input = CreateTag {
+            kind: TagKind::Tag,
+            name: " campaign ".to_string(),
+        };
+        let tag = state.create_tag(user.id, input).await?;
+
+        assert_eq!(tag.name, "campaign");
+
+        let input = CreateTag {
+            kind: TagKind::Tag,
+            name: "campaign".to_string(),
+        };
+        assert!(matches!(
+            state.create_tag(user.id, input).await,
+            Err(AppError::Conflict(_))
+        ));
+
+        // a folder may share the name of a tag
+        let input = CreateTag {
+            kind: TagKind::Folder,
+            name: "campaign".to_string(),
+        };
+        let folder = state.create_tag(user.id, input).await?;
+
+        let input = CreateTag {
+            kind: TagKind::Tag,
+            name: "".to_string(),
+        };
+        assert!(state.create_tag(user.id, input).await.is_err());
+
+        let input = RenameTag {
+            name: "spring".to_string(),
+        };
+        let tag = state.rename_tag(user.id, tag.id, input).await?;
+
+        assert_eq!(tag.name, "spring");
+
+        let input = ListTags {
+            kind: Some(TagKind::Folder),
+        };
+        let tags = state.list_tags(user.id, input).await?;
+
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].id, folder.id);
+        assert_eq!(
+            state.list_tags(user.id, ListTags::default()).await?.len(),
+            2
+        );
+
+        state.delete_tag(user.id, folder.id).await?;
+
+        assert!(matches!(
+            state.delete_tag(user.id, folder.id).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn set_url_tags_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let create = |kind, name: &str| CreateTag {
+            kind,
+            name: name.to_string(),
+        };
+        let tag = state.create_tag(user.id, create(TagKind::Tag, "a")).await?;
+        let folder = state
+            .create_tag(user.id, create(TagKind::Folder, "work"))
+            .await?;
+
+        let tagged = state
+            .shorten(user.id, CreateUrl::new("https://www.baidu.com/"))
+            .await?;
+        let mut input = CreateUrl::new("https://www.qq.com/");
+        input.note = Some("Spring sale flyer".to_string());
+        let noted = state.shorten(user.id, input).await?;
+
+        let input = ListUrls {
+            q: Some("sale".to_string()),
+            ..Default::default()
+        };
+        let ret = state.get_all_url(user.id, input).await?;
+
+        assert_eq!(ret.items.len(), 1);
+        assert_eq!(ret.items[0].short, noted.output);
+        assert_eq!(ret.items[0].note.as_deref(), Some("Spring sale flyer"));
+
+        let input = AssignTags {
+            tags: vec![tag.id, folder.id, tag.id],
+        };
+        let tags = state.set_url_tags(user.id, &tagged.output, input).await?;
+
+        assert_eq!(tags.len(), 2);
+
+        let input = ListUrls {
+            tag: Some(tag.id),
+            ..Default::default()
+        };
+        let ret = state.get_all_url(user.id, input).await?;
+
+        assert_eq!(ret.items.len(), 1);
+        assert_eq!(ret.items[0].short, tagged.output);
+
+        let input = ListUrls {
+            folder: Some(folder.id),
+            ..Default::default()
+        };
+        assert_eq!(state.get_all_url(user.id, input).await?.items.len(), 1);
+
+        // a tag id is not a folder
+        let input = ListUrls {
+            folder: Some(tag.id),
+            ..Default::default()
+        };
+        assert!(state.get_all_url(user.id, input).await?.items.is_empty());
+
+        let input = AssignTags {
+            tags: vec![tag.id, 10_000],
+        };
+        assert!(matches!(
+            state.set_url_tags(user.id, &tagged.output, input).await,
+            Err(AppError::NotFound(_))
+        ));
+        // the failed assignment changed nothing
+        assert_eq!(state.get_url_tags(user.id, &tagged.output).await?.len(), 2);
+
+        state.delete_tag(user.id, tag.id).await?;
+
+        assert_eq!(state.get_url_tags(user.id, &tagged.output).await?.len(), 1);
+
+        Ok(())
+    }
+}
@@ -1,6 +1,14 @@
-use crate::{AppError, AppState};
-use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use super::{hash_password, verify_password};
+use crate::{
+    merge_query, normalize_url,
+    targeting::{choose_variant, evaluate, is_web_uri},
+    AppError, AppLinks, AppState, Preview, Rule, Strategy, TagKind, UrlUniqueness, Variant,
+    Visitor,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::{types::Json, FromRow, PgConnection, QueryBuilder};
+use std::{collections::BTreeMap, fmt, net::IpAddr};
 
 #[derive(Serialize, Deserialize, FromRow)]
 #[serde(rename_all = "camelCase")]
@@ -9,158 +17,2200 @@ pub struct Url {
     pub short: String,
     pub user_id: i64,
     pub url: String,
+    pub redirect_status: i16,
+    pub activate_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_clicks: Option<i64>,
+    pub clicks: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub title: Option<String>,
+    // set when the destination was blocked after the url was created
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub disabled_reason: Option<String>,
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    pub remaining_uses: Option<i64>,
+    pub rules: Json<Vec<Rule>>,
+    pub variants: Json<Vec<Variant>>,
+    pub sticky: bool,
+    // fetched in the background after the destination is set
+    pub preview: Option<Json<Preview>>,
+    pub preview_fetched_at: Option<DateTime<Utc>>,
+    // result of the last destination check
+    pub health_status: Option<i16>,
+    pub health_latency_ms: Option<i32>,
+    pub health_checked_at: Option<DateTime<Utc>>,
+    pub health_failures: i32,
+    // set after too many failed checks in a row, cleared by the next success
+    pub broken_at: Option<DateTime<Utc>>,
+    pub note: Option<String>,
+    pub params: Json<BTreeMap<String, String>>,
+    pub pass_query: bool,
+    // the custom domain the short code lives on, none for the default host
+    pub domain_id: Option<i64>,
+    pub app_links: Option<Json<AppLinks>>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct CreateUrl {
     pub url: String,
+    // one of 301, 302, 307, 308, default 302
+    #[serde(default)]
+    pub redirect_status: Option<u16>,
+    // user chosen short code, random when missing
+    #[serde(default)]
+    pub alias: Option<String>,
+    // the url does not resolve before this time
+    #[serde(default)]
+    pub activate_at: Option<DateTime<Utc>>,
+    // the url is gone after this time
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    // the url is gone after this many clicks
+    #[serde(default)]
+    pub max_clicks: Option<i64>,
+    #[serde(default)]
+    pub title: Option<String>,
+    // free text only the owner sees
+    #[serde(default)]
+    pub note: Option<String>,
+    // remove the configured tracking params from the url
+    #[serde(default)]
+    pub strip_tracking: bool,
+    // visitors must enter it before they are redirected
+    #[serde(default)]
+    pub password: Option<String>,
+    // the url resolves this many times and is gone after, 1 makes a one time link
+    #[serde(default)]
+    pub uses: Option<i64>,
+    // visitors matching a rule go to its url instead
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    // visitors not matching any rule are split between these instead of the url
+    #[serde(default)]
+    pub variants: Vec<Variant>,
+    // a returning visitor gets the same variant again
+    #[serde(default)]
+    pub sticky: bool,
+    // set as `utm_*` params on the destination
+    #[serde(default)]
+    pub utm: Option<Utm>,
+    // query params set on the destination when visitors are redirected
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    // pass the query string of the short url on to the destination
+    #[serde(default)]
+    pub pass_query: bool,
+    // verified custom domain of the user like `go.acme.com`, the default host when missing
+    #[serde(default)]
+    pub domain: Option<String>,
+    // app uris tried on ios and android before falling back to the url
+    #[serde(default)]
+    pub app_links: Option<AppLinks>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Utm {
+    pub source: Option<String>,
+    pub medium: Option<String>,
+    pub campaign: Option<String>,
+    pub term: Option<String>,
+    pub content: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct UpdateUrl {
+    pub url: Option<String>,
+    pub redirect_status: Option<u16>,
+    // `null` removes the schedule, limits and title below, a missing field keeps them
+    #[serde(default, deserialize_with = "double_option")]
+    pub activate_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub max_clicks: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub title: Option<Option<String>>,
+    // an empty note removes it
+    pub note: Option<String>,
+    #[serde(default)]
+    pub strip_tracking: bool,
+    // an empty password removes the protection
+    pub password: Option<String>,
+    // resets the remaining uses
+    #[serde(default, deserialize_with = "double_option")]
+    pub uses: Option<Option<i64>>,
+    // replaces all rules
+    pub rules: Option<Vec<Rule>>,
+    // replaces all variants
+    pub variants: Option<Vec<Variant>>,
+    pub sticky: Option<bool>,
+    // set on top of the params, which replace all params
+    pub utm: Option<Utm>,
+    pub params: Option<BTreeMap<String, String>>,
+    pub pass_query: Option<bool>,
+    // replaces the app links, empty ones remove them
+    pub app_links: Option<AppLinks>,
+}
+
+// the url a visit is redirected to, with the name of the variant when one was picked
+#[derive(Debug, PartialEq, Eq)]
+pub struct Destination<'a> {
+    pub url: &'a str,
+    pub variant: Option<&'a str>,
+}
+
+// a synthetic visit to dry-run the rules of a url, `rules` are tested instead of the stored ones
+#[derive(Serialize, Deserialize, Default)]
+pub struct TestRules {
+    pub rules: Option<Vec<Rule>>,
+    pub user_agent: Option<String>,
+    pub accept_language: Option<String>,
+    // the country is looked up from the ip when it is not given
+    pub ip: Option<IpAddr>,
+    pub country: Option<String>,
+    pub time: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RuleMatch {
+    // index of the matched rule, none when the default url is used
+    pub rule: Option<usize>,
+    pub url: String,
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct Output {
-    pub output: String,
+pub struct UnlockUrl {
+    pub password: String,
+}
+
+// how the owner api addresses a url, short codes are only unique per host so a url on a custom
+// domain needs its host as well
+#[derive(Debug, Clone, Copy)]
+pub struct UrlKey<'a> {
+    pub short: &'a str,
+    pub domain: Option<&'a str>,
+}
+
+// `?domain=go.acme.com` of the owner routes, the default host without it
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DomainQuery {
+    pub domain: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ListUrls {
+    // search in url, short code, title and note
+    pub q: Option<String>,
+    // only urls with this tag or in this folder
+    pub tag: Option<i64>,
+    pub folder: Option<i64>,
+    #[serde(default)]
+    pub sort: UrlSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    // `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UrlSort {
+    #[default]
+    CreatedAt,
+    Clicks,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UrlList {
+    pub items: Vec<Url>,
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct MoreOutput {
-    pub output: Vec<Output>,
+pub struct Output {
+    pub output: String,
 }
 
+const REDIRECT_STATUS: [u16; 4] = [301, 302, 307, 308];
+const DEFAULT_REDIRECT_STATUS: u16 = 302;
+const ALIAS_MIN_LEN: usize = 3;
+const ALIAS_MAX_LEN: usize = 64;
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+const MAX_PARAMS: usize = 32;
+const MAX_RULES: usize = 32;
+const MAX_PARAM_KEY: usize = 64;
+const MAX_PARAM_VALUE: usize = 512;
+// aliases which would shadow the routes in `get_router`
+const RESERVED_ALIASES: [&str; 12] = [
+    "admin", "broken", "change", "delete", "domains", "export", "login", "profile", "short",
+    "signup", "tags", "urls",
+];
+
 impl AppState {
     pub async fn shorten(&self, user_id: i64, input: CreateUrl) -> Result<Output, AppError> {
-        let short = nanoid::nanoid!(6);
-        let url: Url = sqlx::query_as(
-            "
-            INSERT INTO urls (short, user_id ,url) VALUES ($1, $2, $3) ON CONFLICT(url) DO UPDATE SET url=EXCLUDED.url RETURNING *
+        let mut tx = self.pool.begin().await?;
+
+        let url = self.shorten_with(&mut tx, user_id, &input).await?;
+
+        tx.commit().await?;
+
+        self.queue_previews(vec![url.id]);
+
+        Ok(Output::new(url.short))
+    }
+
+    // shorten the url on the given connection, the caller owns the transaction
+    pub(crate) async fn shorten_with(
+        &self,
+        conn: &mut PgConnection,
+        user_id: i64,
+        input: &CreateUrl,
+    ) -> Result<Url, AppError> {
+        input.validate()?;
+
+        // store the canonical url so equal destinations are found by the lookup below
+        let input = &CreateUrl {
+            url: normalize_url(&self.config.validation, &input.url, input.strip_tracking)?,
+            rules: self.normalize_rules(&input.rules, input.strip_tracking)?,
+            variants: self.normalize_variants(&input.variants, input.strip_tracking)?,
+            params: build_params(input.params.clone(), input.utm.as_ref())?,
+            utm: None,
+            app_links: self.normalize_app_links(input.app_links.clone(), input.strip_tracking)?,
+            ..input.clone()
+        };
+        self.screen_url(&input.url)?;
+
+        let domain_id = match &input.domain {
+            Some(host) => Some(self.find_user_domain(user_id, host).await?.id),
+            None => None,
+        };
+
+        let password_hash = input.password.as_deref().map(hash_password).transpose()?;
+        let password_hash = password_hash.as_deref();
+
+        if let Some(alias) = &input.alias {
+            let url = insert_url(conn, None, alias, user_id, input, password_hash, domain_id)
+                .await
+                .map_err(|e| {
+                    if is_short_conflict(&e) {
+                        AppError::Conflict(format!("{} by alias already exists", alias))
+                    } else {
+                        e.into()
+                    }
+                })?;
+
+            return Ok(url);
+        }
+
+        // urls with their own options always get a new short code
+        if self.config.shortener.url_uniqueness == UrlUniqueness::PerUser && !input.has_options() {
+            // serialize shorten calls of the same user so the lookup below stays valid
+            sqlx::query("SELECT pg_advisory_xact_lock($1)")
+                .bind(user_id)
+                .execute(&mut *conn)
+                .await?;
+
+            let url: Option<Url> = sqlx::query_as(
+                "
+                SELECT * FROM urls WHERE user_id = $1 AND url = $2
+                    AND domain_id IS NOT DISTINCT FROM $3 AND plain AND disabled_at IS NULL
+                ORDER BY id LIMIT 1
+                ",
+            )
+            .bind(user_id)
+            .bind(&input.url)
+            .bind(domain_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+            if let Some(url) = url {
+                return Ok(url);
+            }
+        }
+
+        self.insert_generated(conn, user_id, input, password_hash, domain_id)
+            .await
+    }
+
+    // insert the url with a generated short code, retry on collision and grow the code length
+    // when random codes keep colliding
+    async fn insert_generated(
+        &self,
+        conn: &mut PgConnection,
+        user_id: i64,
+        input: &CreateUrl,
+        password_hash: Option<&str>,
+        domain_id: Option<i64>,
+    ) -> Result<Url, AppError> {
+        let mut retries = 0;
+
+        loop {
+            let length = self.generator.length();
+            let (id, short) = match self.generator.strategy() {
+                Strategy::Random => (None, self.generator.random()),
+                Strategy::Sequential => {
+                    let id: i64 = sqlx::query_scalar("SELECT nextval('urls_id_seq')")
+                        .fetch_one(&mut *conn)
+                        .await?;
+                    (Some(id), self.generator.encode(id))
+                }
+            };
+
+            if is_reserved(&short) {
+                continue;
+            }
+
+            // savepoint, a failed insert must not abort the outer transaction
+            let mut sp = sqlx::Connection::begin(&mut *conn).await?;
+            let ret = insert_url(
+                &mut sp,
+                id,
+                &short,
+                user_id,
+                input,
+                password_hash,
+                domain_id,
+            )
+            .await;
+
+            match ret {
+                Ok(url) => {
+                    sp.commit().await?;
+                    return Ok(url);
+                }
+                Err(e) if is_short_conflict(&e) => {
+                    sp.rollback().await?;
+                    retries += 1;
+                    if self.generator.strategy() == Strategy::Random
+                        && retries > self.generator.max_retries()
+                    {
+                        let length = self.generator.grow(length)?;
+                        self.save_code_length(length).await?;
+                        retries = 0;
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    // pick up the length random codes have grown to on any instance
+    pub async fn load_code_length(&self) -> Result<(), AppError> {
+        let length: Option<i32> = sqlx::query_scalar("SELECT length FROM code_length")
+            .fetch_optional(&self.pool)
+            .await?;
+
+        if let Some(length) = length {
+            self.generator.resume(length as usize);
+        }
+
+        Ok(())
+    }
+
+    async fn save_code_length(&self, length: usize) -> Result<(), AppError> {
+        let length: i32 = sqlx::query_scalar(
             "
+            INSERT INTO code_length (length) VALUES ($1)
+            ON CONFLICT (id) DO UPDATE SET length = GREATEST(code_length.length, $1)
+            RETURNING length
+            ",
         )
-        .bind(short)
-        .bind(user_id)
-        .bind(input.url)
+        .bind(length as i32)
         .fetch_one(&self.pool)
         .await?;
 
-        Ok(Output::new(url.short))
+        self.generator.resume(length as usize);
+
+        Ok(())
+    }
+
+    pub async fn get_url<'a>(
+        &self,
+        user_id: i64,
+        key: impl Into<UrlKey<'a>>,
+    ) -> Result<Url, AppError> {
+        let key = key.into();
+        let domain_id = self.key_domain_id(user_id, key).await?;
+
+        let url = sqlx::query_as(
+            "
+            SELECT * FROM urls
+            WHERE user_id = $1 AND short = $2 AND domain_id IS NOT DISTINCT FROM $3
+            ",
+        )
+        .bind(user_id)
+        .bind(key.short)
+        .bind(domain_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by url not find", key)))?;
+
+        Ok(url)
+    }
+
+    // the domain of the key, which must be one of the user
+    pub(crate) async fn key_domain_id(
+        &self,
+        user_id: i64,
+        key: UrlKey<'_>,
+    ) -> Result<Option<i64>, AppError> {
+        let Some(host) = key.domain else {
+            return Ok(None);
+        };
+
+        let id = sqlx::query_scalar(
+            "
+            SELECT id FROM domains WHERE user_id = $1 AND host = $2
+            ",
+        )
+        .bind(user_id)
+        .bind(host.trim_end_matches('.').to_ascii_lowercase())
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by url not find", key)))?;
+
+        Ok(Some(id))
     }
 
-    pub async fn get_url(&self, user_id: i64, id: String) -> Result<Output, AppError> {
+    pub async fn update_url<'a>(
+        &self,
+        user_id: i64,
+        key: impl Into<UrlKey<'a>>,
+        input: UpdateUrl,
+    ) -> Result<Url, AppError> {
+        let key = key.into();
+        let domain_id = self.key_domain_id(user_id, key).await?;
+
+        let mut tx = self.pool.begin().await?;
+
+        // locked until the update below, so concurrent updates do not overwrite each other
         let url: Url = sqlx::query_as(
             "
-            SELECT * FROM urls WHERE user_id = $1 AND short = $2
+            SELECT * FROM urls
+            WHERE user_id = $1 AND short = $2 AND domain_id IS NOT DISTINCT FROM $3
+            FOR UPDATE
             ",
         )
         .bind(user_id)
-        .bind(id)
-        .fetch_one(&self.pool)
+        .bind(key.short)
+        .bind(domain_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by url not find", key)))?;
+
+        let redirect_status = input.redirect_status.unwrap_or(url.redirect_status as u16);
+        let activate_at = input.activate_at.unwrap_or(url.activate_at);
+        let expires_at = input.expires_at.unwrap_or(url.expires_at);
+        let max_clicks = input.max_clicks.unwrap_or(url.max_clicks);
+        let remaining_uses = input.uses.unwrap_or(url.remaining_uses);
+
+        validate_redirect_status(redirect_status)?;
+        validate_schedule(key.short, activate_at, expires_at, max_clicks)?;
+        validate_uses(key.short, input.uses.flatten())?;
+
+        let target = match input.url {
+            Some(target) => {
+                let target = normalize_url(&self.config.validation, &target, input.strip_tracking)?;
+                self.screen_url(&target)?;
+                target
+            }
+            None => url.url,
+        };
+
+        let rules = match input.rules {
+            Some(rules) => self.normalize_rules(&rules, input.strip_tracking)?,
+            None => url.rules.0,
+        };
+
+        let variants = match input.variants {
+            Some(variants) => self.normalize_variants(&variants, input.strip_tracking)?,
+            None => url.variants.0,
+        };
+
+        let params = match (input.params, input.utm.as_ref()) {
+            (None, None) => url.params.0,
+            (params, utm) => build_params(params.unwrap_or(url.params.0), utm)?,
+        };
+
+        let app_links = match input.app_links {
+            Some(apps) => {
+                apps.validate().map_err(AppError::UserError)?;
+                self.normalize_app_links(Some(apps), input.strip_tracking)?
+                    .map(Json)
+            }
+            None => url.app_links,
+        };
+
+        let password_hash = match input.password.as_deref() {
+            Some("") => None,
+            Some(password) => Some(hash_password(password)?),
+            None => url.password_hash,
+        };
+
+        let title = input.title.unwrap_or(url.title);
+        let note = match input.note {
+            Some(note) if note.is_empty() => None,
+            Some(note) => Some(note),
+            None => url.note,
+        };
+        let pass_query = input.pass_query.unwrap_or(url.pass_query);
+
+        // the same conditions as `CreateUrl::has_options`, on the values after the update
+        let plain = redirect_status == DEFAULT_REDIRECT_STATUS
+            && activate_at.is_none()
+            && expires_at.is_none()
+            && max_clicks.is_none()
+            && title.is_none()
+            && note.is_none()
+            && password_hash.is_none()
+            && remaining_uses.is_none()
+            && rules.is_empty()
+            && variants.is_empty()
+            && params.is_empty()
+            && !pass_query
+            && app_links.is_none();
+
+        let url: Url = sqlx::query_as(
+            "
+            UPDATE urls SET url = $3, redirect_status = $4, activate_at = $5, expires_at = $6, max_clicks = $7, title = $8, password_hash = $9, remaining_uses = $10, rules = $11, variants = $12, sticky = $13, note = $14, params = $15, pass_query = $16, app_links = $17, plain = $18, updated_at = CURRENT_TIMESTAMP,
+                preview = CASE WHEN url = $3 THEN preview END,
+                preview_fetched_at = CASE WHEN url = $3 THEN preview_fetched_at END,
+                screening_exempt = screening_exempt AND url = $3 AND rules = $11 AND variants = $12
+                    AND app_links IS NOT DISTINCT FROM $17
+            WHERE id = $1 AND user_id = $2 RETURNING *
+            ",
+        )
+        .bind(url.id)
+        .bind(user_id)
+        .bind(target)
+        .bind(redirect_status as i16)
+        .bind(activate_at)
+        .bind(expires_at)
+        .bind(max_clicks)
+        .bind(title)
+        .bind(password_hash)
+        .bind(remaining_uses)
+        .bind(Json(rules))
+        .bind(Json(variants))
+        .bind(input.sticky.unwrap_or(url.sticky))
+        .bind(note)
+        .bind(Json(params))
+        .bind(pass_query)
+        .bind(app_links)
+        .bind(plain)
+        .fetch_one(&mut *tx)
         .await?;
 
-        Ok(Output::new(url.url))
+        tx.commit().await?;
+
+        if url.preview_fetched_at.is_none() {
+            self.queue_previews(vec![url.id]);
+        }
+
+        Ok(url)
+    }
+
+    pub async fn test_rules<'a>(
+        &self,
+        user_id: i64,
+        key: impl Into<UrlKey<'a>>,
+        input: TestRules,
+    ) -> Result<RuleMatch, AppError> {
+        let url = self.get_url(user_id, key).await?;
+
+        let rules = match input.rules {
+            Some(rules) => self.normalize_rules(&rules, false)?,
+            None => url.rules.0,
+        };
+
+        let country = input.country.or_else(|| {
+            let ip = input.ip?;
+            self.country(ip)
+        });
+        let visitor = Visitor {
+            user_agent: input.user_agent,
+            accept_language: input.accept_language,
+            country,
+            time: input.time.unwrap_or_else(Utc::now),
+        };
+
+        let ret = match evaluate(&rules, &visitor) {
+            Some((i, target)) => RuleMatch {
+                rule: Some(i),
+                url: target.to_string(),
+            },
+            None => RuleMatch {
+                rule: None,
+                url: url.url,
+            },
+        };
+
+        Ok(ret)
+    }
+
+    // the public short url visitors open
+    pub fn short_url(&self, short: &str) -> String {
+        match &self.config.server.base_url {
+            Some(base) => format!("{}/{}", base.trim_end_matches('/'), short),
+            None => format!("http://localhost:{}/{}", self.config.server.port, short),
+        }
+    }
+
+    pub fn country(&self, ip: IpAddr) -> Option<String> {
+        self.geoip
+            .as_ref()
+            .and_then(|geoip| geoip.lookup(ip))
+            .map(|c| c.to_string())
+    }
+
+    // rule urls pass the same validation and screening as the default url
+    fn normalize_rules(&self, rules: &[Rule], strip_tracking: bool) -> Result<Vec<Rule>, AppError> {
+        if rules.len() > MAX_RULES {
+            return Err(AppError::UserError(format!(
+                "{} by rules exceed the limit {}",
+                rules.len(),
+                MAX_RULES
+            )));
+        }
+
+        rules
+            .iter()
+            .map(|rule| {
+                rule.validate().map_err(AppError::UserError)?;
+                let url = normalize_url(&self.config.validation, &rule.url, strip_tracking)?;
+                self.screen_url(&url)?;
+                Ok(Rule {
+                    url,
+                    ..rule.clone()
+                })
+            })
+            .collect()
+    }
+
+    // variant urls pass the same validation and screening as the default url
+    fn normalize_variants(
+        &self,
+        variants: &[Variant],
+        strip_tracking: bool,
+    ) -> Result<Vec<Variant>, AppError> {
+        Variant::validate(variants).map_err(AppError::UserError)?;
+
+        variants
+            .iter()
+            .map(|v| {
+                let url = normalize_url(&self.config.validation, &v.url, strip_tracking)?;
+                self.screen_url(&url)?;
+                Ok(Variant { url, ..v.clone() })
+            })
+            .collect()
+    }
+
+    // app uris which are web urls pass the same validation and screening as the default url,
+    // custom schemes are kept as they are
+    fn normalize_app_links(
+        &self,
+        apps: Option<AppLinks>,
+        strip_tracking: bool,
+    ) -> Result<Option<AppLinks>, AppError> {
+        let Some(mut apps) = apps.filter(|apps| !apps.is_empty()) else {
+            return Ok(None);
+        };
+
+        for uri in [&mut apps.ios, &mut apps.android].into_iter().flatten() {
+            if is_web_uri(uri) {
+                *uri = normalize_url(&self.config.validation, uri, strip_tracking)?;
+                self.screen_url(uri)?;
+            }
+        }
+
+        Ok(Some(apps))
     }
 
-    pub async fn get_all_url(&self, user_id: i64) -> Result<MoreOutput, AppError> {
-        let url: Vec<Url> = sqlx::query_as(
+    pub async fn delete_url<'a>(
+        &self,
+        user_id: i64,
+        key: impl Into<UrlKey<'a>>,
+    ) -> Result<Url, AppError> {
+        let key = key.into();
+        let domain_id = self.key_domain_id(user_id, key).await?;
+
+        let url = sqlx::query_as(
             "
-            SELECT * FROM urls WHERE user_id = $1
+            DELETE FROM urls
+            WHERE user_id = $1 AND short = $2 AND domain_id IS NOT DISTINCT FROM $3
+            RETURNING *
             ",
         )
         .bind(user_id)
-        .fetch_all(&self.pool)
+        .bind(key.short)
+        .bind(domain_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by url not find", key)))?;
+
+        Ok(url)
+    }
+
+    // the url on the default host
+    pub async fn find_url_by_short(&self, short: &str) -> Result<Option<Url>, AppError> {
+        self.find_url_on(None, short).await
+    }
+
+    // the url in the namespace of a custom domain, the default host when none
+    pub async fn find_url_on(
+        &self,
+        domain_id: Option<i64>,
+        short: &str,
+    ) -> Result<Option<Url>, AppError> {
+        let url = sqlx::query_as(
+            "
+            SELECT * FROM urls WHERE short = $1 AND domain_id IS NOT DISTINCT FROM $2
+            ",
+        )
+        .bind(short)
+        .bind(domain_id)
+        .fetch_optional(&self.pool)
         .await?;
 
-        let output = url.iter().map(|v| Output::new(v.url.clone())).collect();
+        Ok(url)
+    }
 
-        Ok(MoreOutput { output })
+    pub async fn resolve_url(&self, short: &str, password: Option<&str>) -> Result<Url, AppError> {
+        self.resolve_url_on(None, short, password).await
     }
-}
 
-impl Output {
-    pub fn new(id: impl Into<String>) -> Self {
-        Self { output: id.into() }
+    // find the url for a public visit and count the click, protected urls need the password
+    pub async fn resolve_url_on(
+        &self,
+        domain_id: Option<i64>,
+        short: &str,
+        password: Option<&str>,
+    ) -> Result<Url, AppError> {
+        let url = self
+            .find_url_on(domain_id, short)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("{} by url not find", short)))?;
+
+        url.check_visit(short)?;
+
+        if let Some(password_hash) = &url.password_hash {
+            let is_valid = match password {
+                Some(password) => verify_password(password, password_hash)?,
+                None => false,
+            };
+            if !is_valid {
+                return Err(AppError::Unauthorized(format!(
+                    "{} by url needs the password",
+                    short
+                )));
+            }
+        }
+
+        // the checks above are repeated in the update, so concurrent visitors of a limited url
+        // cannot both take its last use
+        let updated = sqlx::query_as(
+            "
+            UPDATE urls SET clicks = clicks + 1, remaining_uses = remaining_uses - 1
+            WHERE id = $1 AND disabled_at IS NULL
+                AND (activate_at IS NULL OR activate_at <= CURRENT_TIMESTAMP)
+                AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+                AND (max_clicks IS NULL OR clicks < max_clicks)
+                AND (remaining_uses IS NULL OR remaining_uses > 0)
+            RETURNING *
+            ",
+        )
+        .bind(url.id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if let Some(url) = updated {
+            return Ok(url);
+        }
+
+        // the url changed in between, report whatever stopped the update
+        match self.find_url_on(domain_id, short).await? {
+            Some(url) => url.check_visit(short)?,
+            None => return Err(AppError::NotFound(format!("{} by url not find", short))),
+        }
+
+        Err(AppError::Gone(format!("{} by url is used up", short)))
+    }
+
+    pub async fn get_all_url(&self, user_id: i64, input: ListUrls) -> Result<UrlList, AppError> {
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let column = match input.sort {
+            UrlSort::CreatedAt => "created_at",
+            UrlSort::Clicks => "clicks",
+        };
+        let (op, order) = match input.order {
+            SortOrder::Asc => (">", "ASC"),
+            SortOrder::Desc => ("<", "DESC"),
+        };
+
+        let mut builder = QueryBuilder::new("SELECT * FROM urls WHERE user_id = ");
+        builder.push_bind(user_id);
+
+        if let Some(q) = input.q.filter(|q| !q.is_empty()) {
+            let pattern = format!("%{}%", escape_like(&q));
+            builder
+                .push(" AND (url ILIKE ")
+                .push_bind(pattern.clone())
+                .push(" OR short ILIKE ")
+                .push_bind(pattern.clone())
+                .push(" OR title ILIKE ")
+                .push_bind(pattern.clone())
+                .push(" OR note ILIKE ")
+                .push_bind(pattern)
+                .push(")");
+        }
+
+        for (id, kind) in [(input.tag, TagKind::Tag), (input.folder, TagKind::Folder)] {
+            if let Some(id) = id {
+                builder
+                    .push(" AND EXISTS (SELECT 1 FROM url_tags JOIN tags ON tags.id = url_tags.tag_id WHERE url_tags.url_id = urls.id AND tags.id = ")
+                    .push_bind(id)
+                    .push(" AND tags.kind = ")
+                    .push_bind(kind)
+                    .push(")");
+            }
+        }
+
+        if let Some(cursor) = input.cursor {
+            let (key, id) = parse_cursor(&cursor)?;
+            builder.push(format!(" AND ({}, id) {} (", column, op));
+            match input.sort {
+                UrlSort::CreatedAt => {
+                    let time = DateTime::from_timestamp_micros(key).ok_or_else(|| {
+                        AppError::UserError(format!("{} by cursor is invalid", cursor))
+                    })?;
+                    builder.push_bind(time)
+                }
+                UrlSort::Clicks => builder.push_bind(key),
+            };
+            builder.push(", ").push_bind(id).push(")");
+        }
+
+        builder
+            .push(format!(
+                " ORDER BY {} {}, id {} LIMIT ",
+                column, order, order
+            ))
+            .push_bind(limit + 1);
+
+        let mut items: Vec<Url> = builder.build_query_as().fetch_all(&self.pool).await?;
+
+        let next_cursor = if items.len() as i64 > limit {
+            items.truncate(limit as usize);
+            items.last().map(|url| {
+                let key = match input.sort {
+                    UrlSort::CreatedAt => url.created_at.timestamp_micros(),
+                    UrlSort::Clicks => url.clicks,
+                };
+                format!("{}_{}", key, url.id)
+            })
+        } else {
+            None
+        };
+
+        Ok(UrlList { items, next_cursor })
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use anyhow::Result;
+impl CreateUrl {
+    fn validate(&self) -> Result<(), AppError> {
+        validate_redirect_status(self.redirect_status.unwrap_or(DEFAULT_REDIRECT_STATUS))?;
 
-    #[tokio::test]
-    async fn shorten_should_work() -> Result<()> {
-        let (_tdb, state) = AppState::new_for_test().await?;
+        if let Some(alias) = &self.alias {
+            validate_alias(alias)?;
+        }
 
-        let email = "Meng@123.com";
+        validate_uses(&self.url, self.uses)?;
 
-        let user = state
-            .find_user_by_email(email)
-            .await?
-            .expect("user should exists");
+        if let Some(apps) = &self.app_links {
+            apps.validate().map_err(AppError::UserError)?;
+        }
 
-        let url = "www.baidu.com";
-        let input = CreateUrl::new(url);
+        if self.password.as_ref().is_some_and(|p| p.is_empty()) {
+            return Err(AppError::UserError(format!(
+                "{} by url password must not be empty",
+                self.url
+            )));
+        }
 
-        let url = state.shorten(user.id, input).await?;
+        validate_schedule(
+            &self.url,
+            self.activate_at,
+            self.expires_at,
+            self.max_clicks,
+        )
+    }
 
-        assert_eq!(url.output.len(), 6);
+    fn has_options(&self) -> bool {
+        self.redirect_status.is_some()
+            || self.activate_at.is_some()
+            || self.expires_at.is_some()
+            || self.max_clicks.is_some()
+            || self.title.is_some()
+            || self.note.is_some()
+            || self.password.is_some()
+            || self.uses.is_some()
+            || !self.rules.is_empty()
+            || !self.variants.is_empty()
+            || self.utm.is_some()
+            || !self.params.is_empty()
+            || self.pass_query
+            || self.app_links.is_some()
+    }
+}
 
-        Ok(())
+impl<'a> UrlKey<'a> {
+    pub fn new(short: &'a str, domain: Option<&'a str>) -> Self {
+        Self { short, domain }
     }
+}
 
-    #[tokio::test]
-    async fn get_url_should_work() -> Result<()> {
-        let (_tdb, state) = AppState::new_for_test().await?;
+impl<'a> From<&'a str> for UrlKey<'a> {
+    fn from(short: &'a str) -> Self {
+        Self::new(short, None)
+    }
+}
 
-        let email = "Meng@123.com";
+impl<'a> From<&'a String> for UrlKey<'a> {
+    fn from(short: &'a String) -> Self {
+        Self::new(short, None)
+    }
+}
 
-        let user = state
-            .find_user_by_email(email)
-            .await?
-            .expect("user should exists");
+impl fmt::Display for UrlKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.domain {
+            Some(domain) => write!(f, "{}/{}", domain, self.short),
+            None => write!(f, "{}", self.short),
+        }
+    }
+}
 
-        let url = "www.baidu.com";
-        let input = CreateUrl::new(url);
+impl Url {
+    // why a visit of the url is refused, the password is checked apart
+    fn check_visit(&self, short: &str) -> Result<(), AppError> {
+        let now = Utc::now();
 
-        let id = state.shorten(user.id, input).await?;
+        if self.disabled_at.is_some() {
+            return Err(AppError::Forbidden(format!("{} by url is disabled", short)));
+        }
 
-        assert_eq!(id.output.len(), 6);
+        if self.activate_at.is_some_and(|v| now < v) {
+            return Err(AppError::NotFound(format!(
+                "{} by url is not active",
+                short
+            )));
+        }
 
-        let ret = state.get_url(user.id, id.output).await?;
+        if self.expires_at.is_some_and(|v| now >= v) {
+            return Err(AppError::Gone(format!("{} by url is expired", short)));
+        }
+
+        if self.max_clicks.is_some_and(|v| self.clicks >= v) {
+            return Err(AppError::Gone(format!(
+                "{} by url reached max clicks",
+                short
+            )));
+        }
 
-        assert_eq!(ret.output, url);
+        if self.remaining_uses == Some(0) {
+            return Err(AppError::Gone(format!("{} by url is used up", short)));
+        }
 
         Ok(())
     }
 
-    #[tokio::test]
-    async fn get_all_url() -> Result<()> {
-        let (_tdb, state) = AppState::new_for_test().await?;
+    // the url of the first matching rule, otherwise a variant, otherwise the default url, `sticky`
+    // is the variant the visitor got before
+    pub fn destination(&self, visitor: &Visitor, sticky: Option<&str>) -> Destination<'_> {
+        if let Some((_, url)) = evaluate(&self.rules, visitor) {
+            return Destination { url, variant: None };
+        }
 
-        let email = "Meng@123.com";
+        let sticky = sticky.filter(|_| self.sticky);
+        match choose_variant(&self.variants, sticky, &mut rand::thread_rng()) {
+            Some(v) => Destination {
+                url: &v.url,
+                variant: Some(&v.name),
+            },
+            None => Destination {
+                url: &self.url,
+                variant: None,
+            },
+        }
+    }
 
-        let user = state
-            .find_user_by_email(email)
-            .await?
-            .expect("user should exists");
+    // the app uri for the platform of the visitor, opened before the web destination
+    pub fn app_link(&self, visitor: &Visitor) -> Option<&str> {
+        self.app_links
+            .as_ref()
+            .and_then(|apps| apps.0.choose(visitor))
+    }
 
-        let mut url = "www.baidu.com";
-        let mut input = CreateUrl::new(url);
+    // the query of the visit is passed on when enabled, the params of the url win over it
+    pub fn location(&self, target: &str, query: Option<&str>) -> String {
+        let passed: Vec<(String, String)> = query
+            .filter(|_| self.pass_query)
+            .map(|q| {
+                url::form_urlencoded::parse(q.as_bytes())
+                    .into_owned()
+                    .collect()
+            })
+            .unwrap_or_default();
 
-        state.shorten(user.id, input).await?;
+        let target = merge_query(target, passed.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        merge_query(
+            &target,
+            self.params.iter().map(|(k, v)| (k.as_str(), v.as_str())),
+        )
+    }
+}
 
-        url = "www.360.com";
-        input = CreateUrl::new(url);
+impl Output {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self { output: id.into() }
+    }
+}
 
-        state.shorten(user.id, input).await?;
+pub fn validate_alias(alias: &str) -> Result<(), AppError> {
+    if alias.len() < ALIAS_MIN_LEN || alias.len() > ALIAS_MAX_LEN {
+        return Err(AppError::UserError(format!(
+            "{} by alias length must be between {} and {}",
+            alias, ALIAS_MIN_LEN, ALIAS_MAX_LEN
+        )));
+    }
+
+    if !alias
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(AppError::UserError(format!(
+            "{} by alias only allows letters, digits, '-' and '_'",
+            alias
+        )));
+    }
+
+    if is_reserved(alias) {
+        return Err(AppError::UserError(format!(
+            "{} by alias is reserved",
+            alias
+        )));
+    }
+
+    Ok(())
+}
+
+fn validate_redirect_status(redirect_status: u16) -> Result<(), AppError> {
+    if !REDIRECT_STATUS.contains(&redirect_status) {
+        return Err(AppError::UserError(format!(
+            "{} is not a redirect status",
+            redirect_status
+        )));
+    }
+
+    Ok(())
+}
+
+fn validate_schedule(
+    name: &str,
+    activate_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    max_clicks: Option<i64>,
+) -> Result<(), AppError> {
+    if let (Some(activate_at), Some(expires_at)) = (activate_at, expires_at) {
+        if activate_at >= expires_at {
+            return Err(AppError::UserError(format!(
+                "{} by url expires before it is active",
+                name
+            )));
+        }
+    }
+
+    if max_clicks.is_some_and(|v| v <= 0) {
+        return Err(AppError::UserError(format!(
+            "{} by url max clicks must be positive",
+            name
+        )));
+    }
+
+    Ok(())
+}
+
+// the params with the utm fields set as `utm_*` params
+fn build_params(
+    mut params: BTreeMap<String, String>,
+    utm: Option<&Utm>,
+) -> Result<BTreeMap<String, String>, AppError> {
+    if let Some(utm) = utm {
+        for (key, value) in [
+            ("utm_source", &utm.source),
+            ("utm_medium", &utm.medium),
+            ("utm_campaign", &utm.campaign),
+            ("utm_term", &utm.term),
+            ("utm_content", &utm.content),
+        ] {
+            if let Some(value) = value {
+                params.insert(key.to_string(), value.clone());
+            }
+        }
+    }
+
+    if params.len() > MAX_PARAMS {
+        return Err(AppError::UserError(format!(
+            "{} by params exceed the limit {}",
+            params.len(),
+            MAX_PARAMS
+        )));
+    }
+    if let Some((key, _)) = params
+        .iter()
+        .find(|(k, v)| k.is_empty() || k.len() > MAX_PARAM_KEY || v.len() > MAX_PARAM_VALUE)
+    {
+        return Err(AppError::UserError(format!(
+            "{} by param is empty or too long",
+            key
+        )));
+    }
+
+    Ok(params)
+}
+
+// `null` deserializes to `Some(None)`, a missing field to `None` through `#[serde(default)]`
+fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+fn validate_uses(name: &str, uses: Option<i64>) -> Result<(), AppError> {
+    if uses.is_some_and(|v| v <= 0) {
+        return Err(AppError::UserError(format!(
+            "{} by url uses must be positive",
+            name
+        )));
+    }
+
+    Ok(())
+}
+
+// the cursor is `{sort key}_{id}` of the last url in the page
+fn parse_cursor(cursor: &str) -> Result<(i64, i64), AppError> {
+    cursor
+        .split_once('_')
+        .and_then(|(key, id)| Some((key.parse().ok()?, id.parse().ok()?)))
+        .ok_or_else(|| AppError::UserError(format!("{} by cursor is invalid", cursor)))
+}
+
+fn escape_like(q: &str) -> String {
+    q.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn is_reserved(short: &str) -> bool {
+    RESERVED_ALIASES.contains(&short.to_ascii_lowercase().as_str())
+}
+
+async fn insert_url(
+    conn: &mut PgConnection,
+    id: Option<i64>,
+    short: &str,
+    user_id: i64,
+    input: &CreateUrl,
+    password_hash: Option<&str>,
+    domain_id: Option<i64>,
+) -> Result<Url, sqlx::Error> {
+    let redirect_status = input.redirect_status.unwrap_or(DEFAULT_REDIRECT_STATUS);
+
+    sqlx::query_as(
+        "
+        INSERT INTO urls (id, short, user_id ,url, redirect_status, activate_at, expires_at, max_clicks, title, password_hash, remaining_uses, rules, variants, sticky, note, params, pass_query, domain_id, app_links, plain)
+        VALUES (COALESCE($1, nextval('urls_id_seq')), $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20) RETURNING *
+        ",
+    )
+    .bind(id)
+    .bind(short)
+    .bind(user_id)
+    .bind(&input.url)
+    .bind(redirect_status as i16)
+    .bind(input.activate_at)
+    .bind(input.expires_at)
+    .bind(input.max_clicks)
+    .bind(&input.title)
+    .bind(password_hash)
+    .bind(input.uses)
+    .bind(Json(&input.rules))
+    .bind(Json(&input.variants))
+    .bind(input.sticky)
+    .bind(&input.note)
+    .bind(Json(&input.params))
+    .bind(input.pass_query)
+    .bind(domain_id)
+    .bind(input.app_links.as_ref().map(Json))
+    .bind(!input.has_options())
+    .fetch_one(conn)
+    .await
+}
+
+// short codes are unique per host
+fn is_short_conflict(e: &sqlx::Error) -> bool {
+    matches!(
+        e.as_database_error().and_then(|e| e.constraint()),
+        Some("urls_default_short_key" | "urls_domain_short_key")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CreateUser, Device, ShortGenerator};
+    use anyhow::Result;
+    use chrono::Duration;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn shorten_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let email = "Meng@123.com";
+
+        let user = state
+            .find_user_by_email(email)
+            .await?
+            .expect("user should exists");
+
+        let url = "https://www.baidu.com/";
+        let input = CreateUrl::new(url);
+
+        let url = state.shorten(user.id, input).await?;
+
+        assert_eq!(url.output.len(), 6);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shorten_should_only_reuse_plain_url() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let url = "https://www.baidu.com/";
+        let id = state.shorten(user.id, CreateUrl::new(url)).await?;
+
+        assert_eq!(
+            state.shorten(user.id, CreateUrl::new(url)).await?.output,
+            id.output
+        );
+
+        // an expiration set later makes the url no longer plain
+        let input = UpdateUrl {
+            expires_at: Some(Some(Utc::now() + Duration::days(1))),
+            ..Default::default()
+        };
+        state.update_url(user.id, &id.output, input).await?;
+
+        let other = state.shorten(user.id, CreateUrl::new(url)).await?;
+        assert_ne!(other.output, id.output);
+
+        // and a disabled url is never handed out
+        sqlx::query("UPDATE urls SET disabled_at = CURRENT_TIMESTAMP WHERE short = $1")
+            .bind(&other.output)
+            .execute(&state.pool)
+            .await?;
+
+        let ret = state.shorten(user.id, CreateUrl::new(url)).await?;
+        assert_ne!(ret.output, id.output);
+        assert_ne!(ret.output, other.output);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shorten_should_normalize_url() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let first = state
+            .shorten(user.id, CreateUrl::new("https://www.baidu.com/?q=1"))
+            .await?;
+
+        let mut input = CreateUrl::new("HTTPS://WWW.BAIDU.COM:443/?q=1&utm_source=mail");
+        input.strip_tracking = true;
+        let second = state.shorten(user.id, input).await?;
+
+        // the canonical url is found by the per user lookup
+        assert_eq!(first.output, second.output);
+
+        let ret = state
+            .shorten(user.id, CreateUrl::new("www.baidu.com"))
+            .await;
+
+        assert!(matches!(ret, Err(AppError::InvalidUrl(_))));
+
+        let input = UpdateUrl {
+            url: Some("ftp://www.baidu.com".to_string()),
+            ..Default::default()
+        };
+        let ret = state.update_url(user.id, &first.output, input).await;
+
+        assert!(matches!(ret, Err(AppError::InvalidUrl(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_url_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let email = "Meng@123.com";
+
+        let user = state
+            .find_user_by_email(email)
+            .await?
+            .expect("user should exists");
+
+        let url = "https://www.baidu.com/";
+        let input = CreateUrl::new(url);
+
+        let id = state.shorten(user.id, input).await?;
+
+        assert_eq!(id.output.len(), 6);
+
+        let ret = state.get_url(user.id, &id.output).await?;
+
+        assert_eq!(ret.url, url);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn find_url_by_short_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let email = "Meng@123.com";
+
+        let user = state
+            .find_user_by_email(email)
+            .await?
+            .expect("user should exists");
+
+        let url = "https://www.baidu.com/";
+        let mut input = CreateUrl::new(url);
+        input.redirect_status = Some(301);
+
+        let id = state.shorten(user.id, input).await?;
+
+        let ret = state
+            .find_url_by_short(&id.output)
+            .await?
+            .expect("url should exists");
+
+        assert_eq!(ret.url, url);
+        assert_eq!(ret.user_id, user.id);
+        assert_eq!(ret.redirect_status, 301);
+
+        let ret = state.find_url_by_short("nothing").await?;
+
+        assert!(ret.is_none());
+
+        let mut input = CreateUrl::new("https://www.360.com/");
+        input.redirect_status = Some(200);
+
+        assert!(state.shorten(user.id, input).await.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_alias_should_work() {
+        assert!(validate_alias("spring-sale").is_ok());
+        assert!(validate_alias("Spring_Sale_2024").is_ok());
+        assert!(validate_alias("ab").is_err());
+        assert!(validate_alias(&"a".repeat(65)).is_err());
+        assert!(validate_alias("spring sale").is_err());
+        assert!(validate_alias("spring/sale").is_err());
+        assert!(validate_alias("login").is_err());
+        assert!(validate_alias("URLS").is_err());
+    }
+
+    #[tokio::test]
+    async fn shorten_with_alias_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let email = "Meng@123.com";
+
+        let user = state
+            .find_user_by_email(email)
+            .await?
+            .expect("user should exists");
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.alias = Some("spring-sale".to_string());
+
+        let id = state.shorten(user.id, input).await?;
+
+        assert_eq!(id.output, "spring-sale");
+
+        let ret = state.get_url(user.id, &id.output).await?;
+
+        assert_eq!(ret.url, "https://www.baidu.com/");
+
+        let mut input = CreateUrl::new("https://www.360.com/");
+        input.alias = Some("spring-sale".to_string());
+
+        let ret = state.shorten(user.id, input).await;
+
+        assert!(matches!(ret, Err(AppError::Conflict(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shorten_should_be_owned_per_user() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let other = state
+            .create_user(CreateUser {
+                name: "TeamAlice".to_string(),
+                email: "Alice@123.com".to_string(),
+                password: "123456".to_string(),
+            })
+            .await?;
+
+        let url = "https://www.baidu.com/";
+
+        let id = state.shorten(user.id, CreateUrl::new(url)).await?;
+        let other_id = state.shorten(other.id, CreateUrl::new(url)).await?;
+
+        assert_ne!(id.output, other_id.output);
+        assert_eq!(
+            state
+                .get_all_url(user.id, ListUrls::default())
+                .await?
+                .items
+                .len(),
+            1
+        );
+        assert_eq!(
+            state
+                .get_all_url(other.id, ListUrls::default())
+                .await?
+                .items
+                .len(),
+            1
+        );
+
+        let ret = state.get_url(other.id, &other_id.output).await?;
+
+        assert_eq!(ret.url, url);
+
+        // shorten the same url again returns the same short code
+        let again = state.shorten(user.id, CreateUrl::new(url)).await?;
+
+        assert_eq!(again.output, id.output);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shorten_without_uniqueness_should_work() -> Result<()> {
+        let (_tdb, mut state) = AppState::new_for_test().await?;
+
+        Arc::get_mut(&mut state.inner)
+            .expect("state should not be shared")
+            .config
+            .shortener
+            .url_uniqueness = UrlUniqueness::None;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let url = "https://www.baidu.com/";
+
+        let id = state.shorten(user.id, CreateUrl::new(url)).await?;
+        let again = state.shorten(user.id, CreateUrl::new(url)).await?;
+
+        assert_ne!(id.output, again.output);
+        assert_eq!(
+            state
+                .get_all_url(user.id, ListUrls::default())
+                .await?
+                .items
+                .len(),
+            2
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shorten_should_grow_length_on_collision() -> Result<()> {
+        let (_tdb, mut state) = AppState::new_for_test().await?;
+
+        let inner = Arc::get_mut(&mut state.inner).expect("state should not be shared");
+        inner.config.shortener.alphabet = "ab".to_string();
+        inner.config.shortener.length = 1;
+        inner.generator = ShortGenerator::new(&inner.config.shortener)?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let urls = [
+            "https://www.baidu.com/",
+            "https://www.360.com/",
+            "https://www.qq.com/",
+        ];
+        for url in urls {
+            let id = state.shorten(user.id, CreateUrl::new(url)).await?;
+            let ret = state.get_url(user.id, &id.output).await?;
+            assert_eq!(ret.url, url);
+        }
+
+        // only two codes of length 1 exist
+        assert!(state.generator.length() >= 2);
+
+        // a restarted instance goes on with the grown length
+        let inner = Arc::get_mut(&mut state.inner).expect("state should not be shared");
+        inner.generator = ShortGenerator::new(&inner.config.shortener)?;
+        assert_eq!(state.generator.length(), 1);
+        state.load_code_length().await?;
+        assert!(state.generator.length() >= 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shorten_sequential_should_work() -> Result<()> {
+        let (_tdb, mut state) = AppState::new_for_test().await?;
+
+        let inner = Arc::get_mut(&mut state.inner).expect("state should not be shared");
+        inner.config.shortener.strategy = Strategy::Sequential;
+        inner.generator = ShortGenerator::new(&inner.config.shortener)?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let id = state
+            .shorten(user.id, CreateUrl::new("https://www.baidu.com/"))
+            .await?;
+
+        let url = state
+            .find_url_by_short(&id.output)
+            .await?
+            .expect("url should exists");
+
+        assert_eq!(url.short, state.generator.encode(url.id));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resolve_url_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.max_clicks = Some(2);
+        let id = state.shorten(user.id, input).await?;
+
+        assert_eq!(state.resolve_url(&id.output, None).await?.clicks, 1);
+        assert_eq!(state.resolve_url(&id.output, None).await?.clicks, 2);
+
+        let ret = state.resolve_url(&id.output, None).await;
+        assert!(matches!(ret, Err(AppError::Gone(_))));
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.activate_at = Some(Utc::now() + Duration::hours(1));
+        let id = state.shorten(user.id, input).await?;
+
+        let ret = state.resolve_url(&id.output, None).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.activate_at = Some(Utc::now() - Duration::hours(2));
+        input.expires_at = Some(Utc::now() - Duration::hours(1));
+        let id = state.shorten(user.id, input).await?;
+
+        let ret = state.resolve_url(&id.output, None).await;
+        assert!(matches!(ret, Err(AppError::Gone(_))));
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.activate_at = Some(Utc::now());
+        input.expires_at = Some(Utc::now() - Duration::hours(1));
+
+        assert!(state.shorten(user.id, input).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resolve_limited_url_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.uses = Some(2);
+        let id = state.shorten(user.id, input).await?;
+
+        let url = state.resolve_url(&id.output, None).await?;
+        assert_eq!(url.remaining_uses, Some(1));
+
+        let url = state.resolve_url(&id.output, None).await?;
+        assert_eq!(url.remaining_uses, Some(0));
+
+        let ret = state.resolve_url(&id.output, None).await;
+        assert!(matches!(ret, Err(AppError::Gone(_))));
+
+        // concurrent visitors of a one time url, only one of them gets through
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.uses = Some(1);
+        let id = state.shorten(user.id, input).await?;
+
+        let tasks: Vec<_> = (0..10)
+            .map(|_| {
+                let state = state.clone();
+                let short = id.output.clone();
+                tokio::spawn(async move { state.resolve_url(&short, None).await })
+            })
+            .collect();
+
+        let mut resolved = 0;
+        for task in tasks {
+            match task.await? {
+                Ok(_) => resolved += 1,
+                Err(e) => assert!(matches!(e, AppError::Gone(_))),
+            }
+        }
+
+        assert_eq!(resolved, 1);
+
+        let input = UpdateUrl {
+            uses: Some(Some(1)),
+            ..Default::default()
+        };
+        state.update_url(user.id, &id.output, input).await?;
+
+        assert!(state.resolve_url(&id.output, None).await.is_ok());
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.uses = Some(0);
+        assert!(state.shorten(user.id, input).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rules_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.rules = vec![Rule {
+            device: Some(vec![Device::Ios]),
+            language: None,
+            country: None,
+            hours: None,
+            url: "HTTPS://APPS.APPLE.COM/app".to_string(),
+        }];
+        let id = state.shorten(user.id, input).await?;
+
+        let url = state.get_url(user.id, &id.output).await?;
+
+        assert_eq!(url.rules[0].url, "https://apps.apple.com/app");
+
+        let input = TestRules {
+            user_agent: Some("Mozilla/5.0 (iPhone; CPU iPhone OS 17_0)".to_string()),
+            ..Default::default()
+        };
+        let ret = state.test_rules(user.id, &id.output, input).await?;
+
+        assert_eq!(ret.rule, Some(0));
+        assert_eq!(ret.url, "https://apps.apple.com/app");
+
+        let input = TestRules {
+            rules: Some(vec![Rule {
+                device: None,
+                language: None,
+                country: Some(vec!["CN".to_string()]),
+                hours: None,
+                url: "https://www.baidu.com/cn".to_string(),
+            }]),
+            country: Some("us".to_string()),
+            ..Default::default()
+        };
+        let ret = state.test_rules(user.id, &id.output, input).await?;
+
+        assert_eq!(ret.rule, None);
+        assert_eq!(ret.url, "https://www.baidu.com/");
+
+        // a rule without conditions is rejected
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.rules = vec![Rule {
+            device: None,
+            language: None,
+            country: None,
+            hours: None,
+            url: "https://www.baidu.com/".to_string(),
+        }];
+        assert!(state.shorten(user.id, input).await.is_err());
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.rules = vec![
+            Rule {
+                device: Some(vec![Device::Ios]),
+                language: None,
+                country: None,
+                hours: None,
+                url: "https://apps.apple.com/app".to_string(),
+            };
+            MAX_RULES + 1
+        ];
+        assert!(matches!(
+            state.shorten(user.id, input).await,
+            Err(AppError::UserError(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shorten_with_variants_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let variant = |name: &str, url: &str| Variant {
+            name: name.to_string(),
+            url: url.to_string(),
+            weight: 1,
+        };
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.variants = vec![
+            variant("a", "https://WWW.Baidu.com/a"),
+            variant("b", "https://www.baidu.com/b"),
+        ];
+        input.sticky = true;
+        let id = state.shorten(user.id, input).await?;
+        let url = state.get_url(user.id, &id.output).await?;
+
+        assert_eq!(url.variants[0].url, "https://www.baidu.com/a");
+        assert!(url.sticky);
+
+        // variant urls never share the short code of the plain url
+        let plain = state
+            .shorten(user.id, CreateUrl::new("https://www.baidu.com/"))
+            .await?;
+        assert_ne!(plain.output, id.output);
+
+        let visitor = Visitor {
+            user_agent: None,
+            accept_language: None,
+            country: None,
+            time: Utc::now(),
+        };
+        let dest = url.destination(&visitor, Some("b"));
+
+        assert_eq!(dest.url, "https://www.baidu.com/b");
+        assert_eq!(dest.variant, Some("b"));
+
+        let dest = url.destination(&visitor, Some("gone"));
+        assert!(dest.variant.is_some());
+
+        let input = UpdateUrl {
+            variants: Some(vec![]),
+            ..Default::default()
+        };
+        let url = state.update_url(user.id, &id.output, input).await?;
+        let dest = url.destination(&visitor, Some("b"));
+
+        assert_eq!(dest.url, "https://www.baidu.com/");
+        assert_eq!(dest.variant, None);
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.variants = vec![
+            variant("a", "https://www.baidu.com/a"),
+            variant("a", "https://www.baidu.com/b"),
+        ];
+        assert!(matches!(
+            state.shorten(user.id, input).await,
+            Err(AppError::UserError(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shorten_with_params_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let mut input = CreateUrl::new("https://www.baidu.com/s?q=1&utm_source=old");
+        input.utm = Some(Utm {
+            source: Some("flyer".to_string()),
+            campaign: Some("spring".to_string()),
+            ..Default::default()
+        });
+        input.params = BTreeMap::from([("ref".to_string(), "print".to_string())]);
+        input.pass_query = true;
+        let id = state.shorten(user.id, input).await?;
+        let url = state.get_url(user.id, &id.output).await?;
+
+        assert_eq!(url.params.len(), 3);
+        assert_eq!(url.params["utm_source"], "flyer");
+        assert_eq!(
+            url.location(&url.url, Some("gclid=x&ref=visitor")),
+            "https://www.baidu.com/s?q=1&gclid=x&ref=print&utm_campaign=spring&utm_source=flyer"
+        );
+
+        // plain urls never share the short code of a url with params
+        let plain = state
+            .shorten(
+                user.id,
+                CreateUrl::new("https://www.baidu.com/s?q=1&utm_source=old"),
+            )
+            .await?;
+        assert_ne!(plain.output, id.output);
+
+        let input = UpdateUrl {
+            params: Some(BTreeMap::new()),
+            utm: Some(Utm {
+                medium: Some("email".to_string()),
+                ..Default::default()
+            }),
+            pass_query: Some(false),
+            ..Default::default()
+        };
+        let url = state.update_url(user.id, &id.output, input).await?;
+
+        assert_eq!(
+            url.location(&url.url, Some("gclid=x")),
+            "https://www.baidu.com/s?q=1&utm_source=old&utm_medium=email"
+        );
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.params = BTreeMap::from([("".to_string(), "x".to_string())]);
+        assert!(matches!(
+            state.shorten(user.id, input).await,
+            Err(AppError::UserError(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resolve_protected_url_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.password = Some("secret".to_string());
+        let id = state.shorten(user.id, input).await?;
+
+        let ret = state.resolve_url(&id.output, None).await;
+        assert!(matches!(ret, Err(AppError::Unauthorized(_))));
+
+        let ret = state.resolve_url(&id.output, Some("wrong")).await;
+        assert!(matches!(ret, Err(AppError::Unauthorized(_))));
+
+        let url = state.resolve_url(&id.output, Some("secret")).await?;
+        assert_eq!(url.clicks, 1);
+
+        // a plain url is never answered with the protected one
+        let plain = state
+            .shorten(user.id, CreateUrl::new("https://www.baidu.com/"))
+            .await?;
+        assert_ne!(plain.output, id.output);
+
+        // the hash never leaves the server
+        assert!(!serde_json::to_string(&url)?.contains("argon2"));
+
+        let input = UpdateUrl {
+            password: Some("".to_string()),
+            ..Default::default()
+        };
+        state.update_url(user.id, &id.output, input).await?;
+
+        assert!(state.resolve_url(&id.output, None).await.is_ok());
+
+        let mut input = CreateUrl::new("https://www.baidu.com/");
+        input.password = Some("".to_string());
+        assert!(state.shorten(user.id, input).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_url_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let id = state
+            .shorten(user.id, CreateUrl::new("https://www.baidu.com/"))
+            .await?;
+        let url = state.get_url(user.id, &id.output).await?;
+
+        let input = UpdateUrl {
+            url: Some("https://www.360.com/".to_string()),
+            redirect_status: Some(308),
+            ..Default::default()
+        };
+        let ret = state.update_url(user.id, &id.output, input).await?;
+
+        assert_eq!(ret.id, url.id);
+        assert_eq!(ret.url, "https://www.360.com/");
+        assert_eq!(ret.redirect_status, 308);
+        assert!(ret.updated_at >= url.updated_at);
+
+        let input = UpdateUrl {
+            redirect_status: Some(200),
+            ..Default::default()
+        };
+        assert!(state.update_url(user.id, &id.output, input).await.is_err());
+
+        let input: UpdateUrl = serde_json::from_str(
+            r#"{"expires_at": "2099-01-01T00:00:00Z", "max_clicks": 5, "title": "Search"}"#,
+        )?;
+        let ret = state.update_url(user.id, &id.output, input).await?;
+
+        assert!(ret.expires_at.is_some());
+        assert_eq!(ret.max_clicks, Some(5));
+
+        // `null` clears a field, a missing field keeps it
+        let input: UpdateUrl = serde_json::from_str(r#"{"expires_at": null, "title": null}"#)?;
+        let ret = state.update_url(user.id, &id.output, input).await?;
+
+        assert!(ret.expires_at.is_none());
+        assert!(ret.title.is_none());
+        assert_eq!(ret.max_clicks, Some(5));
+
+        let ret = state
+            .update_url(user.id + 1, &id.output, UpdateUrl::default())
+            .await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_url_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let id = state
+            .shorten(user.id, CreateUrl::new("https://www.baidu.com/"))
+            .await?;
+
+        // only the owner can delete the url
+        let ret = state.delete_url(user.id + 1, &id.output).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let url = state.delete_url(user.id, &id.output).await?;
+
+        assert_eq!(url.short, id.output);
+        assert!(state.find_url_by_short(&id.output).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_all_url() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let email = "Meng@123.com";
+
+        let user = state
+            .find_user_by_email(email)
+            .await?
+            .expect("user should exists");
+
+        let mut url = "https://www.baidu.com/";
+        let mut input = CreateUrl::new(url);
+
+        state.shorten(user.id, input).await?;
+
+        url = "https://www.360.com/";
+        input = CreateUrl::new(url);
+
+        state.shorten(user.id, input).await?;
+
+        let ret = state.get_all_url(user.id, ListUrls::default()).await?;
+
+        assert_eq!(ret.items.len(), 2);
+        assert!(ret.next_cursor.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_all_url_should_paginate() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_email("Meng@123.com")
+            .await?
+            .expect("user should exists");
+
+        let urls = [
+            "https://www.baidu.com/",
+            "https://www.360.com/",
+            "https://www.qq.com/",
+        ];
+        for url in urls {
+            let mut input = CreateUrl::new(url);
+            input.title = Some(format!("{} title", url));
+            state.shorten(user.id, input).await?;
+        }
+
+        let input = ListUrls {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = state.get_all_url(user.id, input).await?;
+
+        assert_eq!(page.items.len(), 2);
+        assert_eq!(page.items[0].url, "https://www.qq.com/");
+        assert_eq!(page.items[1].url, "https://www.360.com/");
+
+        let input = ListUrls {
+            limit: Some(2),
+            cursor: page.next_cursor,
+            ..Default::default()
+        };
+        let page = state.get_all_url(user.id, input).await?;
+
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].url, "https://www.baidu.com/");
+        assert!(page.next_cursor.is_none());
+
+        let input = ListUrls {
+            q: Some("360.com/ TITLE".to_string()),
+            ..Default::default()
+        };
+        let page = state.get_all_url(user.id, input).await?;
+
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].url, "https://www.360.com/");
+
+        let short = state.get_all_url(user.id, ListUrls::default()).await?.items[2]
+            .short
+            .clone();
+        state.resolve_url(&short, None).await?;
+
+        let input = ListUrls {
+            sort: UrlSort::Clicks,
+            limit: Some(1),
+            ..Default::default()
+        };
+        let page = state.get_all_url(user.id, input).await?;
 
-        let ret = state.get_all_url(user.id).await?;
+        assert_eq!(page.items[0].url, "https://www.baidu.com/");
+        assert_eq!(page.items[0].clicks, 1);
 
-        assert_eq!(ret.output.len(), 2);
+        let input = ListUrls {
+            cursor: Some("bad".to_string()),
+            ..Default::default()
+        };
+        assert!(state.get_all_url(user.id, input).await.is_err());
 
         Ok(())
     }
 
     impl CreateUrl {
-        fn new(url: impl Into<String>) -> Self {
-            Self { url: url.into() }
+        pub fn new(url: impl Into<String>) -> Self {
+            Self {
+                url: url.into(),
+                redirect_status: None,
+                alias: None,
+                activate_at: None,
+                expires_at: None,
+                max_clicks: None,
+                title: None,
+                note: None,
+                strip_tracking: false,
+                password: None,
+                uses: None,
+                rules: vec![],
+                variants: vec![],
+                sticky: false,
+                utm: None,
+                params: BTreeMap::new(),
+                pass_query: false,
+                domain: None,
+                app_links: None,
+            }
         }
     }
 }
@@ -1,4 +1,4 @@
-use crate::{AppError, AppState};
+use crate::{util::JWT_DURATION, AppError, AppState};
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
@@ -12,8 +12,12 @@ pub struct User {
     pub id: i64,
     pub name: String,
     pub email: String,
+    #[serde(skip)]
     pub password_hash: String,
     pub created_at: DateTime<Utc>,
+    pub role: String,
+    // tokens signed with an older version are rejected
+    pub token_version: i32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -74,6 +78,73 @@ impl AppState {
         Ok(user)
     }
 
+    pub async fn find_user_by_id(&self, id: i64) -> Result<Option<User>, AppError> {
+        let user = sqlx::query_as(
+            "
+            SELECT * FROM users WHERE id = $1
+            ",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    // the role is read from the database so a revoked admin loses access at once
+    pub async fn is_admin(&self, user_id: i64) -> Result<bool, AppError> {
+        let ret: Option<bool> = sqlx::query_scalar(
+            "
+            SELECT role = 'admin' FROM users WHERE id = $1
+            ",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(ret.unwrap_or(false))
+    }
+
+    // logs out a single session, the other tokens of the user stay valid
+    pub async fn revoke_session(&self, user_id: i64, sid: &str) -> Result<(), AppError> {
+        sqlx::query(
+            "
+            INSERT INTO revoked_sessions (sid, user_id) VALUES ($1, $2)
+            ON CONFLICT (sid) DO NOTHING
+            ",
+        )
+        .bind(sid)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        // tokens older than this have expired, their sessions need no entry any more
+        sqlx::query(
+            "
+            DELETE FROM revoked_sessions
+            WHERE revoked_at < CURRENT_TIMESTAMP - make_interval(secs => $1)
+            ",
+        )
+        .bind(JWT_DURATION as f64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn is_session_revoked(&self, sid: &str) -> Result<bool, AppError> {
+        let ret: bool = sqlx::query_scalar(
+            "
+            SELECT EXISTS(SELECT 1 FROM revoked_sessions WHERE sid = $1)
+            ",
+        )
+        .bind(sid)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(ret)
+    }
+
     pub async fn delete_user_by_email(&self, email: &str) -> Result<User, AppError> {
         let user = sqlx::query_as(
             "
@@ -109,53 +180,31 @@ impl AppState {
         }
     }
 
+    // a new password revokes every token signed before it
     pub async fn change_user_message(
         &self,
-        mut user: User,
+        user: User,
         input: ChangeUser,
     ) -> Result<User, AppError> {
-        if let Some(name) = input.name {
-            sqlx::query(
-                "
-                UPDATE users set name = $1 WHERE email = $2
-                ",
-            )
-            .bind(&name)
-            .bind(&user.email)
-            .execute(&self.pool)
-            .await?;
-            user.name = name;
-        };
-
-        if let Some(password) = input.password {
-            let password_hash = hash_password(&password)?;
-
-            sqlx::query(
-                "
-                UPDATE users set password_hash = $1 WHERE email = $2
-                ",
-            )
-            .bind(&password_hash)
-            .bind(&user.email)
-            .execute(&self.pool)
-            .await?;
-            user.password_hash = password_hash;
-        };
-
-        if let Some(email) = input.email {
-            let user = sqlx::query_as(
-                "
-                UPDATE users set email = $1 WHERE email = $2 RETURNING *
-                ",
-            )
-            .bind(email)
-            .bind(user.email)
-            .fetch_one(&self.pool)
-            .await?;
-            Ok(user)
-        } else {
-            Ok(user)
-        }
+        let password_hash = input.password.as_deref().map(hash_password).transpose()?;
+
+        let user = sqlx::query_as(
+            "
+            UPDATE users SET name = COALESCE($2, name), email = COALESCE($3, email),
+                password_hash = COALESCE($4, password_hash),
+                token_version = token_version + CASE WHEN $4::varchar IS NULL THEN 0 ELSE 1 END
+            WHERE id = $1 RETURNING *
+            ",
+        )
+        .bind(user.id)
+        .bind(input.name)
+        .bind(input.email)
+        .bind(password_hash)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} by user not find", user.email)))?;
+
+        Ok(user)
     }
 }
 
@@ -289,10 +338,12 @@ mod tests {
             Some(password.clone()),
         );
 
+        let version = user.token_version;
         let user = state.change_user_message(user, input).await?;
 
         assert_eq!(user.email, email);
         assert_eq!(user.name, name);
+        assert_eq!(user.token_version, version + 1);
 
         let is_valid = verify_password(&password, &user.password_hash)?;
 
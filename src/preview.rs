@@ -0,0 +1,404 @@
+use crate::{
+    validation::{is_private_host, is_private_ip},
+    AppError, PreviewConfig, UrlError,
+};
+use regex::Regex;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    header, redirect, Client, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+use url::Url;
+
+const MAX_TITLE: usize = 300;
+const MAX_DESCRIPTION: usize = 1000;
+
+static TITLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").expect("regex should be valid"));
+static META: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<meta\s[^>]*>").expect("regex should be valid"));
+static LINK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<link\s[^>]*>").expect("regex should be valid"));
+static ATTR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?s)([a-zA-Z][\w:-]*)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#)
+        .expect("regex should be valid")
+});
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
+pub struct Preview {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub favicon: Option<String>,
+}
+
+// fetches destination pages, only ever connects to public addresses unless configured otherwise
+pub struct Previewer {
+    client: Client,
+    permits: Semaphore,
+    max_bytes: usize,
+    block_private_hosts: bool,
+}
+
+// resolves like the system resolver and drops private addresses, so a public name pointing at
+// an internal host can not be used to reach it
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| !is_private_ip(&addr.ip()))
+                .collect();
+
+            if addrs.is_empty() {
+                return Err(format!("{} by host has no public address", name.as_str()).into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+impl Previewer {
+    pub fn new(config: &PreviewConfig) -> Result<Self, AppError> {
+        let max_redirects = config.max_redirects;
+        let block_private_hosts = config.block_private_hosts;
+
+        // every hop is checked, ip literals never reach the resolver
+        let policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                attempt.error(format!("more than {} redirects", max_redirects))
+            } else if let Err(e) = check_target(attempt.url(), block_private_hosts) {
+                attempt.error(e)
+            } else {
+                attempt.follow()
+            }
+        });
+
+        let mut builder = Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .redirect(policy)
+            .user_agent(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            ));
+        if block_private_hosts {
+            builder = builder.dns_resolver(Arc::new(PublicResolver));
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            permits: Semaphore::new(config.concurrency.max(1)),
+            max_bytes: config.max_bytes,
+            block_private_hosts,
+        })
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<Preview, AppError> {
+        let target =
+            Url::parse(url).map_err(|e| UrlError::Malformed(url.to_string(), e.to_string()))?;
+        check_target(&target, self.block_private_hosts)?;
+
+        let _permit = self.permits.acquire().await.ok();
+
+        let mut res = self
+            .client
+            .get(target)
+            .header(header::ACCEPT, "text/html,application/xhtml+xml")
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let base = res.url().clone();
+        let is_html = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("html"));
+        if !is_html {
+            return Ok(Preview::default());
+        }
+
+        let mut body = vec![];
+        while let Some(chunk) = res.chunk().await? {
+            let rest = self.max_bytes - body.len();
+            body.extend_from_slice(&chunk[..chunk.len().min(rest)]);
+            if body.len() >= self.max_bytes {
+                break;
+            }
+        }
+
+        Ok(parse_preview(&String::from_utf8_lossy(&body), &base))
+    }
+
+    // the status code of the destination, servers rejecting HEAD are asked with GET and the body
+    // is not read
+    pub async fn probe(&self, url: &str) -> Result<StatusCode, AppError> {
+        let target =
+            Url::parse(url).map_err(|e| UrlError::Malformed(url.to_string(), e.to_string()))?;
+        check_target(&target, self.block_private_hosts)?;
+
+        let _permit = self.permits.acquire().await.ok();
+
+        let status = self.client.head(target.clone()).send().await?.status();
+        if status != StatusCode::METHOD_NOT_ALLOWED && status != StatusCode::NOT_IMPLEMENTED {
+            return Ok(status);
+        }
+
+        Ok(self.client.get(target).send().await?.status())
+    }
+}
+
+fn check_target(url: &Url, block_private_hosts: bool) -> Result<(), UrlError> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(UrlError::Scheme(url.scheme().to_string()));
+    }
+
+    let host = url
+        .host()
+        .ok_or_else(|| UrlError::MissingHost(url.to_string()))?;
+    if block_private_hosts && is_private_host(&host) {
+        return Err(UrlError::PrivateHost(host.to_string()));
+    }
+
+    Ok(())
+}
+
+// open graph tags win over the plain html ones, relative links are resolved against `base`
+pub fn parse_preview(html: &str, base: &Url) -> Preview {
+    let mut meta = HashMap::new();
+    for tag in META.find_iter(html) {
+        let attrs = parse_attrs(tag.as_str());
+        let key = attrs.get("property").or_else(|| attrs.get("name"));
+        if let (Some(key), Some(content)) = (key, attrs.get("content")) {
+            meta.entry(key.to_ascii_lowercase())
+                .or_insert_with(|| content.clone());
+        }
+    }
+    let first = |keys: &[&str]| keys.iter().find_map(|k| meta.get(*k).cloned());
+
+    let title = first(&["og:title"])
+        .or_else(|| TITLE.captures(html).map(|c| decode_entities(&c[1])))
+        .or_else(|| first(&["twitter:title"]));
+    let description = first(&["og:description", "description", "twitter:description"]);
+    let image = first(&["og:image", "og:image:url", "twitter:image"]);
+
+    let favicon = LINK
+        .find_iter(html)
+        .map(|tag| parse_attrs(tag.as_str()))
+        .find(|attrs| {
+            attrs
+                .get("rel")
+                .is_some_and(|rel| rel.split_whitespace().any(|r| r == "icon"))
+        })
+        .and_then(|attrs| attrs.get("href").cloned())
+        .unwrap_or_else(|| "/favicon.ico".to_string());
+
+    Preview {
+        title: title
+            .map(|t| clean(&t, MAX_TITLE))
+            .filter(|t| !t.is_empty()),
+        description: description
+            .map(|d| clean(&d, MAX_DESCRIPTION))
+            .filter(|d| !d.is_empty()),
+        image: image.and_then(|i| resolve(base, &i)),
+        favicon: resolve(base, &favicon),
+    }
+}
+
+// attribute names are lowercased, values are entity decoded
+fn parse_attrs(tag: &str) -> HashMap<String, String> {
+    ATTR.captures_iter(tag)
+        .map(|c| {
+            let value = c
+                .get(2)
+                .or_else(|| c.get(3))
+                .or_else(|| c.get(4))
+                .map(|v| v.as_str())
+                .unwrap_or_default();
+            (c[1].to_ascii_lowercase(), decode_entities(value))
+        })
+        .collect()
+}
+
+fn resolve(base: &Url, href: &str) -> Option<String> {
+    let url = base.join(href.trim()).ok()?;
+    matches!(url.scheme(), "http" | "https").then(|| url.into())
+}
+
+// collapse whitespace and cut to `max` chars
+fn clean(text: &str, max: usize) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(max)
+        .collect()
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::{
+        http::header,
+        response::{Html, IntoResponse, Redirect},
+        routing::get,
+        Router,
+    };
+    use tokio::net::TcpListener;
+
+    // a local stand-in for destination pages
+    async fn serve() -> Result<SocketAddr> {
+        let app = Router::new()
+            .route(
+                "/page",
+                get(|| async {
+                    Html(
+                        r#"<html><head>
+<title> Plain &amp; Simple </title>
+<meta property="og:title" content="Open Graph Title">
+<meta name="description" content='A page about "things"'>
+<meta property="og:image" content="/img/cover.png">
+<link rel="shortcut icon" href="/static/icon.png">
+</head><body>hello</body></html>"#,
+                    )
+                }),
+            )
+            .route(
+                "/plain",
+                get(|| async { Html("<title>Only a title</title>") }),
+            )
+            .route("/hop", get(|| async { Redirect::temporary("/page") }))
+            .route("/loop", get(|| async { Redirect::temporary("/loop") }))
+            .route(
+                "/big",
+                get(|| async {
+                    let body = format!("<title>Big</title>{}", "x".repeat(1 << 20));
+                    Html(body)
+                }),
+            )
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(3)).await;
+                    Html("<title>Slow</title>")
+                }),
+            )
+            .route(
+                "/json",
+                get(|| async {
+                    ([(header::CONTENT_TYPE, "application/json")], "{}").into_response()
+                }),
+            );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        Ok(addr)
+    }
+
+    fn config() -> PreviewConfig {
+        PreviewConfig {
+            timeout: 1,
+            max_bytes: 4096,
+            max_redirects: 2,
+            block_private_hosts: false,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_should_work() -> Result<()> {
+        let addr = serve().await?;
+        let previewer = Previewer::new(&config())?;
+
+        let preview = previewer.fetch(&format!("http://{}/hop", addr)).await?;
+
+        assert_eq!(preview.title.as_deref(), Some("Open Graph Title"));
+        assert_eq!(
+            preview.description.as_deref(),
+            Some("A page about \"things\"")
+        );
+        assert_eq!(
+            preview.image,
+            Some(format!("http://{}/img/cover.png", addr))
+        );
+        assert_eq!(
+            preview.favicon,
+            Some(format!("http://{}/static/icon.png", addr))
+        );
+
+        let preview = previewer.fetch(&format!("http://{}/plain", addr)).await?;
+
+        assert_eq!(preview.title.as_deref(), Some("Only a title"));
+        assert_eq!(
+            preview.favicon,
+            Some(format!("http://{}/favicon.ico", addr))
+        );
+
+        // only the first `max_bytes` are read
+        let preview = previewer.fetch(&format!("http://{}/big", addr)).await?;
+        assert_eq!(preview.title.as_deref(), Some("Big"));
+
+        let preview = previewer.fetch(&format!("http://{}/json", addr)).await?;
+        assert_eq!(preview, Preview::default());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetch_should_reject_unsafe() -> Result<()> {
+        let addr = serve().await?;
+        let previewer = Previewer::new(&config())?;
+
+        assert!(previewer
+            .fetch(&format!("http://{}/loop", addr))
+            .await
+            .is_err());
+        assert!(previewer
+            .fetch(&format!("http://{}/slow", addr))
+            .await
+            .is_err());
+
+        let previewer = Previewer::new(&PreviewConfig::default())?;
+        for url in [
+            format!("http://{}/page", addr),
+            "http://localhost/".to_string(),
+            "http://[::1]/".to_string(),
+            "http://169.254.169.254/latest/meta-data/".to_string(),
+        ] {
+            let ret = previewer.fetch(&url).await;
+            assert!(
+                matches!(ret, Err(AppError::InvalidUrl(UrlError::PrivateHost(_)))),
+                "{}",
+                url
+            );
+        }
+
+        assert!(previewer.fetch("ftp://www.baidu.com/").await.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+use crate::AppError;
+use qrcode::{types::Color, EcLevel, QrCode};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+const MIN_SIZE: u32 = 64;
+const MAX_SIZE: u32 = 2048;
+const MAX_MARGIN: u32 = 16;
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+// error correction, `h` still scans with about 30% of the code covered
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QrLevel {
+    L,
+    #[default]
+    M,
+    Q,
+    H,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QrQuery {
+    #[serde(default)]
+    pub format: QrFormat,
+    // width and height in pixels, the modules are scaled by a whole number so the png may be a
+    // little smaller
+    #[serde(default = "default_size")]
+    pub size: u32,
+    #[serde(default)]
+    pub level: QrLevel,
+    // quiet zone around the code in modules
+    #[serde(default = "default_margin")]
+    pub margin: u32,
+    // hex colours like `000000` or `#000000`
+    #[serde(default = "default_fg")]
+    pub fg: String,
+    #[serde(default = "default_bg")]
+    pub bg: String,
+}
+
+impl Default for QrQuery {
+    fn default() -> Self {
+        Self {
+            format: QrFormat::default(),
+            size: default_size(),
+            level: QrLevel::default(),
+            margin: default_margin(),
+            fg: default_fg(),
+            bg: default_bg(),
+        }
+    }
+}
+
+impl QrFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Svg => "image/svg+xml",
+        }
+    }
+}
+
+impl From<QrLevel> for EcLevel {
+    fn from(level: QrLevel) -> Self {
+        match level {
+            QrLevel::L => EcLevel::L,
+            QrLevel::M => EcLevel::M,
+            QrLevel::Q => EcLevel::Q,
+            QrLevel::H => EcLevel::H,
+        }
+    }
+}
+
+// encode `data` as a png or svg image
+pub fn render(data: &str, query: &QrQuery) -> Result<Vec<u8>, AppError> {
+    if !(MIN_SIZE..=MAX_SIZE).contains(&query.size) {
+        return Err(AppError::UserError(format!(
+            "{} by qr size must be between {} and {}",
+            query.size, MIN_SIZE, MAX_SIZE
+        )));
+    }
+    if query.margin > MAX_MARGIN {
+        return Err(AppError::UserError(format!(
+            "{} by qr margin must be at most {}",
+            query.margin, MAX_MARGIN
+        )));
+    }
+    let fg = parse_color(&query.fg)?;
+    let bg = parse_color(&query.bg)?;
+
+    let code = QrCode::with_error_correction_level(data, query.level.into())
+        .map_err(|e| AppError::UserError(format!("{} by qr code failed: {}", data, e)))?;
+    let modules = Modules::new(&code, query.margin);
+
+    match query.format {
+        QrFormat::Png => render_png(&modules, query.size, fg, bg),
+        QrFormat::Svg => Ok(render_svg(&modules, query.size, fg, bg).into_bytes()),
+    }
+}
+
+// the dark modules of the code surrounded by the margin
+struct Modules {
+    width: usize,
+    dark: Vec<bool>,
+}
+
+impl Modules {
+    fn new(code: &QrCode, margin: u32) -> Self {
+        let inner = code.width();
+        let margin = margin as usize;
+        let width = inner + 2 * margin;
+        let colors = code.to_colors();
+
+        let dark = (0..width * width)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                x >= margin
+                    && y >= margin
+                    && x < margin + inner
+                    && y < margin + inner
+                    && colors[(y - margin) * inner + (x - margin)] == Color::Dark
+            })
+            .collect();
+
+        Self { width, dark }
+    }
+
+    fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.dark[y * self.width + x]
+    }
+}
+
+fn render_png(modules: &Modules, size: u32, fg: [u8; 3], bg: [u8; 3]) -> Result<Vec<u8>, AppError> {
+    // at least one pixel per module so large codes stay scannable
+    let scale = (size as usize / modules.width).max(1);
+    let side = modules.width * scale;
+
+    let mut pixels = Vec::with_capacity(side * side * 3);
+    for y in 0..side {
+        for x in 0..side {
+            let color = if modules.is_dark(x / scale, y / scale) {
+                fg
+            } else {
+                bg
+            };
+            pixels.extend_from_slice(&color);
+        }
+    }
+
+    let mut buf = vec![];
+    let mut encoder = png::Encoder::new(&mut buf, side as u32, side as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+
+    Ok(buf)
+}
+
+// one unit per module, the viewer scales it to `size`
+fn render_svg(modules: &Modules, size: u32, fg: [u8; 3], bg: [u8; 3]) -> String {
+    let width = modules.width;
+    let mut path = String::new();
+    for y in 0..width {
+        for x in 0..width {
+            if modules.is_dark(x, y) {
+                let _ = write!(path, "M{} {}h1v1h-1z", x, y);
+            }
+        }
+    }
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}" viewBox="0 0 {width} {width}" shape-rendering="crispEdges">
+<rect width="{width}" height="{width}" fill="{bg}"/>
+<path fill="{fg}" d="{path}"/>
+</svg>
+"#,
+        bg = hex(bg),
+        fg = hex(fg),
+    )
+}
+
+fn parse_color(color: &str) -> Result<[u8; 3], AppError> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    let invalid = || AppError::UserError(format!("{} by colour is not a hex rgb colour", color));
+
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+
+    let mut rgb = [0; 3];
+    for (i, v) in rgb.iter_mut().enumerate() {
+        *v = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+
+    Ok(rgb)
+}
+
+fn hex([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn default_size() -> u32 {
+    256
+}
+
+fn default_margin() -> u32 {
+    4
+}
+
+fn default_fg() -> String {
+    "000000".to_string()
+}
+
+fn default_bg() -> String {
+    "ffffff".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn render_should_work() -> Result<()> {
+        let data = "http://localhost:6688/abc123";
+
+        let png = render(data, &QrQuery::default())?;
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+        let decoder = png::Decoder::new(png.as_slice());
+        let reader = decoder.read_info()?;
+        let info = reader.info();
+        // version 3 is 29 modules, 37 with the margin, scaled 6 times
+        assert_eq!((info.width, info.height), (222, 222));
+
+        let query = QrQuery {
+            format: QrFormat::Svg,
+            level: QrLevel::H,
+            margin: 0,
+            fg: "#FF0000".to_string(),
+            ..Default::default()
+        };
+        let svg = String::from_utf8(render(data, &query)?)?;
+        assert!(svg.contains(r##"fill="#ff0000""##));
+        assert!(svg.contains(r#"width="256""#));
+        // the top left finder pattern starts at the corner without a margin
+        assert!(svg.contains(r#"d="M0 0h1v1h-1z"#));
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_should_reject_invalid() {
+        let data = "http://localhost:6688/abc123";
+
+        for query in [
+            QrQuery {
+                size: 16,
+                ..Default::default()
+            },
+            QrQuery {
+                margin: 100,
+                ..Default::default()
+            },
+            QrQuery {
+                fg: "red".to_string(),
+                ..Default::default()
+            },
+            QrQuery {
+                bg: "#12345g".to_string(),
+                ..Default::default()
+            },
+        ] {
+            assert!(matches!(render(data, &query), Err(AppError::UserError(_))));
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{AppError, BlockKind, BlockRule, ScreeningConfig};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs,
+    sync::{Arc, RwLock},
+};
+use url::Url;
+
+// a screener returns the reason when the destination must not be shortened
+pub trait Screener: Send + Sync {
+    fn screen(&self, url: &Url) -> Option<String>;
+}
+
+enum Rule {
+    Domain(String),
+    Regex(Regex),
+}
+
+// the blocklist table kept in memory, reloaded whenever this process changes the table and on
+// every rescan, other instances see a change once their next rescan runs
+#[derive(Default)]
+pub struct Blocklist {
+    rules: RwLock<Vec<(i64, Rule)>>,
+}
+
+// sha256 hex prefixes of blocked hosts loaded from a file, one per line, `#` starts a comment
+pub struct HashPrefixList {
+    prefixes: HashSet<String>,
+    lengths: BTreeSet<usize>,
+}
+
+impl Blocklist {
+    pub fn reload(&self, rules: &[BlockRule]) -> Result<(), AppError> {
+        let rules = rules
+            .iter()
+            .map(|rule| match rule.kind {
+                BlockKind::Domain => Ok((rule.id, Rule::Domain(rule.pattern.to_ascii_lowercase()))),
+                BlockKind::Regex => Ok((rule.id, Rule::Regex(compile(&rule.pattern)?))),
+            })
+            .collect::<Result<_, AppError>>()?;
+
+        *self.rules.write().expect("blocklist lock poisoned") = rules;
+
+        Ok(())
+    }
+
+    // the id of the first matching rule and the reason
+    pub fn find(&self, url: &Url) -> Option<(i64, String)> {
+        let host = url.host_str()?.trim_end_matches('.');
+        let rules = self.rules.read().expect("blocklist lock poisoned");
+
+        rules.iter().find_map(|(id, rule)| match rule {
+            Rule::Domain(domain) if host == domain || host.ends_with(&format!(".{}", domain)) => {
+                Some((*id, format!("{} by domain is blocked", domain)))
+            }
+            Rule::Regex(re) if re.is_match(url.as_str()) => {
+                Some((*id, format!("{} by pattern is blocked", re.as_str())))
+            }
+            _ => None,
+        })
+    }
+}
+
+impl Screener for Blocklist {
+    fn screen(&self, url: &Url) -> Option<String> {
+        self.find(url).map(|(_, reason)| reason)
+    }
+}
+
+impl HashPrefixList {
+    pub fn load(path: &str) -> Result<Self, AppError> {
+        let data = fs::read_to_string(path).map_err(|e| {
+            AppError::ConfigError(format!(
+                "{} by hash prefix file can not be read: {}",
+                path, e
+            ))
+        })?;
+        let prefixes: HashSet<String> = data
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| line.to_ascii_lowercase())
+            .collect();
+
+        if let Some(prefix) = prefixes
+            .iter()
+            .find(|p| p.len() > 64 || !p.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            return Err(AppError::ConfigError(format!(
+                "{} by hash prefix is not a sha256 hex prefix",
+                prefix
+            )));
+        }
+
+        let lengths = prefixes.iter().map(|p| p.len()).collect();
+
+        Ok(Self { prefixes, lengths })
+    }
+}
+
+impl Screener for HashPrefixList {
+    // the host and each parent domain are hashed, so a listed domain covers its subdomains
+    fn screen(&self, url: &Url) -> Option<String> {
+        let host = url.host_str()?.trim_end_matches('.');
+
+        host_suffixes(host).find_map(|expr| {
+            let hash = format!("{:x}", Sha256::digest(expr.as_bytes()));
+            self.lengths
+                .iter()
+                .any(|len| self.prefixes.contains(&hash[..*len]))
+                .then(|| format!("{} by host is on the hash prefix list", expr))
+        })
+    }
+}
+
+pub fn compile(pattern: &str) -> Result<Regex, AppError> {
+    Regex::new(pattern)
+        .map_err(|e| AppError::UserError(format!("{} by pattern is invalid: {}", pattern, e)))
+}
+
+// the configured screeners, the blocklist always runs first
+pub fn build_screeners(
+    config: &ScreeningConfig,
+    blocklist: Arc<Blocklist>,
+) -> Result<Vec<Arc<dyn Screener>>, AppError> {
+    let mut screeners: Vec<Arc<dyn Screener>> = vec![blocklist];
+
+    if let Some(path) = &config.hash_prefix_file {
+        screeners.push(Arc::new(HashPrefixList::load(path)?));
+    }
+
+    Ok(screeners)
+}
+
+// `a.b.example.com` yields itself, `b.example.com` and `example.com`
+fn host_suffixes(host: &str) -> impl Iterator<Item = &str> {
+    let labels = host.split('.').count();
+    let mut rest = Some(host);
+
+    std::iter::from_fn(move || {
+        let current = rest?;
+        rest = current.split_once('.').map(|(_, r)| r);
+        Some(current)
+    })
+    .take(labels.saturating_sub(1).max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use chrono::Utc;
+
+    fn rule(id: i64, kind: BlockKind, pattern: &str) -> BlockRule {
+        BlockRule {
+            id,
+            kind,
+            pattern: pattern.to_string(),
+            reason: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn blocklist_should_work() -> Result<()> {
+        let blocklist = Blocklist::default();
+        blocklist.reload(&[
+            rule(1, BlockKind::Domain, "Evil.com"),
+            rule(2, BlockKind::Regex, r"/login\.php$"),
+        ])?;
+
+        assert!(blocklist
+            .screen(&Url::parse("https://evil.com/")?)
+            .is_some());
+        assert!(blocklist
+            .screen(&Url::parse("https://www.evil.com/a")?)
+            .is_some());
+        assert!(blocklist
+            .screen(&Url::parse("https://notevil.com/")?)
+            .is_none());
+        assert!(blocklist
+            .screen(&Url::parse("https://bank.example.com/login.php")?)
+            .is_some());
+        assert!(blocklist
+            .screen(&Url::parse("https://www.baidu.com/")?)
+            .is_none());
+
+        assert_eq!(
+            blocklist
+                .find(&Url::parse("https://bank.example.com/login.php")?)
+                .map(|(id, _)| id),
+            Some(2)
+        );
+
+        assert!(blocklist.reload(&[rule(3, BlockKind::Regex, "(")]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn hash_prefix_list_should_work() -> Result<()> {
+        let hash = format!("{:x}", Sha256::digest(b"evil.com"));
+        let path = std::env::temp_dir().join(format!("hash-prefix-{}.txt", std::process::id()));
+        fs::write(&path, format!("# blocked hosts\n{}\n\n", &hash[..8]))?;
+
+        let list = HashPrefixList::load(path.to_str().expect("path should be utf8"))?;
+
+        fs::write(&path, "not-hex\n")?;
+        let ret = HashPrefixList::load(path.to_str().expect("path should be utf8"));
+        assert!(matches!(ret, Err(AppError::ConfigError(_))));
+        fs::remove_file(&path)?;
+        let ret = HashPrefixList::load(path.to_str().expect("path should be utf8"));
+        assert!(matches!(ret, Err(AppError::ConfigError(_))));
+
+        assert!(list.screen(&Url::parse("https://evil.com/")?).is_some());
+        assert!(list.screen(&Url::parse("https://a.b.evil.com/")?).is_some());
+        assert!(list
+            .screen(&Url::parse("https://www.baidu.com/")?)
+            .is_none());
+
+        assert_eq!(
+            host_suffixes("a.b.example.com").collect::<Vec<_>>(),
+            ["a.b.example.com", "b.example.com", "example.com"]
+        );
+        assert_eq!(
+            host_suffixes("localhost").collect::<Vec<_>>(),
+            ["localhost"]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,500 @@
+use chrono::{DateTime, Duration, Timelike, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+const MAX_APP_URI: usize = 2048;
+const UNSAFE_SCHEMES: [&str; 5] = ["blob", "data", "file", "javascript", "vbscript"];
+
+// a rule matches when every condition it has matches, rules are tried in order
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Rule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<Vec<Device>>,
+    // language tags like `zh` or `zh-TW`, matched against the preferred language of the visitor
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<Vec<String>>,
+    // ISO 3166 alpha-2 codes looked up from the visitor ip
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hours: Option<HourRange>,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Device {
+    Ios,
+    Android,
+    Windows,
+    Mac,
+    Linux,
+    Mobile,
+    Desktop,
+}
+
+// from `start` up to `end` exclusive at `utc_offset` hours, wraps around midnight when `start` is
+// after `end`
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct HourRange {
+    pub start: u32,
+    pub end: u32,
+    #[serde(default)]
+    pub utc_offset: i32,
+}
+
+// one destination of a split test, picked with a chance of its share of the total weight
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Variant {
+    // stored in the sticky cookie and on the click
+    pub name: String,
+    pub url: String,
+    pub weight: u32,
+}
+
+// app uris opened instead of the web url on the platform of the visitor
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct AppLinks {
+    // universal link or custom scheme uri like `acme://item/1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ios: Option<String>,
+    // intent url like `intent://item/1#Intent;scheme=acme;package=com.acme.app;end`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub android: Option<String>,
+}
+
+// what the rules know about a visit
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Visitor {
+    pub user_agent: Option<String>,
+    pub accept_language: Option<String>,
+    pub country: Option<String>,
+    pub time: DateTime<Utc>,
+}
+
+impl Rule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.device.is_none()
+            && self.language.is_none()
+            && self.country.is_none()
+            && self.hours.is_none()
+        {
+            return Err(format!("{} by rule has no condition", self.url));
+        }
+
+        if let Some(hours) = &self.hours {
+            if hours.start > 23 || hours.end > 24 || hours.start == hours.end {
+                return Err(format!("{} by rule hours are invalid", self.url));
+            }
+            if !(-14..=14).contains(&hours.utc_offset) {
+                return Err(format!("{} by rule utc offset is invalid", self.url));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn matches(&self, visitor: &Visitor) -> bool {
+        let device = self.device.as_ref().is_none_or(|devices| {
+            let ua = visitor.user_agent.as_deref().unwrap_or_default();
+            devices.iter().any(|d| d.matches(ua))
+        });
+
+        let language = self.language.as_ref().is_none_or(|languages| {
+            preferred_language(visitor.accept_language.as_deref().unwrap_or_default())
+                .is_some_and(|preferred| languages.iter().any(|l| language_matches(l, &preferred)))
+        });
+
+        let country = self.country.as_ref().is_none_or(|countries| {
+            visitor
+                .country
+                .as_ref()
+                .is_some_and(|c| countries.iter().any(|v| v.eq_ignore_ascii_case(c)))
+        });
+
+        let hours = self
+            .hours
+            .as_ref()
+            .is_none_or(|hours| hours.contains(visitor.time));
+
+        device && language && country && hours
+    }
+}
+
+impl Device {
+    fn matches(&self, ua: &str) -> bool {
+        let ua = ua.to_ascii_lowercase();
+        let ios = ["iphone", "ipad", "ipod"].iter().any(|v| ua.contains(v));
+        let android = ua.contains("android");
+        let mobile = ios || android || ua.contains("mobile");
+
+        match self {
+            Self::Ios => ios,
+            Self::Android => android,
+            Self::Windows => ua.contains("windows"),
+            Self::Mac => !ios && (ua.contains("macintosh") || ua.contains("mac os x")),
+            Self::Linux => !android && ua.contains("linux"),
+            Self::Mobile => mobile,
+            Self::Desktop => !mobile && !ua.is_empty(),
+        }
+    }
+}
+
+impl HourRange {
+    fn contains(&self, time: DateTime<Utc>) -> bool {
+        let hour = (time + Duration::hours(self.utc_offset as i64)).hour();
+
+        if self.start < self.end {
+            hour >= self.start && hour < self.end
+        } else {
+            hour >= self.start || hour < self.end
+        }
+    }
+}
+
+impl Variant {
+    pub fn validate(variants: &[Variant]) -> Result<(), String> {
+        let mut names = HashSet::new();
+
+        for v in variants {
+            if v.name.is_empty()
+                || v.name.len() > 32
+                || !v
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(format!(
+                    "{} by variant name only allows up to 32 letters, digits, '-' and '_'",
+                    v.name
+                ));
+            }
+
+            if !names.insert(v.name.as_str()) {
+                return Err(format!("{} by variant name is duplicated", v.name));
+            }
+
+            if v.weight == 0 {
+                return Err(format!("{} by variant weight must be positive", v.name));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) fn is_web_uri(uri: &str) -> bool {
+    url::Url::parse(uri).is_ok_and(|u| matches!(u.scheme(), "http" | "https"))
+}
+
+impl AppLinks {
+    pub fn validate(&self) -> Result<(), String> {
+        for uri in [&self.ios, &self.android].into_iter().flatten() {
+            if uri.len() > MAX_APP_URI {
+                return Err(format!(
+                    "{} by app link must not be longer than {}",
+                    uri, MAX_APP_URI
+                ));
+            }
+
+            // the uri ends up in a script of the interstitial page
+            let scheme = url::Url::parse(uri)
+                .map(|u| u.scheme().to_string())
+                .map_err(|_| format!("{} by app link is not a valid uri", uri))?;
+            if UNSAFE_SCHEMES.contains(&scheme.as_str()) {
+                return Err(format!("{} by app link scheme is not allowed", uri));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ios.is_none() && self.android.is_none()
+    }
+
+    // universal links and app links are plain web urls, screened like the destination
+    pub fn web_uris(&self) -> impl Iterator<Item = &String> {
+        [&self.ios, &self.android]
+            .into_iter()
+            .flatten()
+            .filter(|uri| is_web_uri(uri))
+    }
+
+    // the app uri for the platform of the visitor, none on other platforms
+    pub fn choose(&self, visitor: &Visitor) -> Option<&str> {
+        let ua = visitor.user_agent.as_deref().unwrap_or_default();
+
+        if Device::Ios.matches(ua) {
+            self.ios.as_deref()
+        } else if Device::Android.matches(ua) {
+            self.android.as_deref()
+        } else {
+            None
+        }
+    }
+}
+
+// the variant named by the sticky cookie when it still exists, otherwise a weighted random one
+pub fn choose_variant<'a>(
+    variants: &'a [Variant],
+    sticky: Option<&str>,
+    rng: &mut impl Rng,
+) -> Option<&'a Variant> {
+    if let Some(v) = sticky.and_then(|name| variants.iter().find(|v| v.name == name)) {
+        return Some(v);
+    }
+
+    let total: u64 = variants.iter().map(|v| v.weight as u64).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut n = rng.gen_range(0..total);
+    variants.iter().find(|v| {
+        if n < v.weight as u64 {
+            true
+        } else {
+            n -= v.weight as u64;
+            false
+        }
+    })
+}
+
+// the index and destination of the first matching rule
+pub fn evaluate<'a>(rules: &'a [Rule], visitor: &Visitor) -> Option<(usize, &'a str)> {
+    rules
+        .iter()
+        .enumerate()
+        .find(|(_, rule)| rule.matches(visitor))
+        .map(|(i, rule)| (i, rule.url.as_str()))
+}
+
+// the tag with the highest quality, `zh-CN,zh;q=0.9,en;q=0.8` prefers `zh-cn`
+fn preferred_language(accept_language: &str) -> Option<String> {
+    accept_language
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let tag = parts.next()?.trim().to_ascii_lowercase();
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (!tag.is_empty() && tag != "*" && q > 0.0).then_some((tag, q))
+        })
+        .fold(None, |best: Option<(String, f32)>, (tag, q)| match best {
+            Some((_, best_q)) if best_q >= q => best,
+            _ => Some((tag, q)),
+        })
+        .map(|(tag, _)| tag)
+}
+
+// `zh` matches `zh` and `zh-cn`, `zh-tw` only matches `zh-tw`
+fn language_matches(rule: &str, tag: &str) -> bool {
+    let rule = rule.to_ascii_lowercase();
+    tag == rule || tag.starts_with(&format!("{}-", rule))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) Mobile/15E148";
+    const MAC: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) Safari/605.1.15";
+
+    fn visitor(ua: &str, lang: &str, country: Option<&str>, hour: u32) -> Visitor {
+        Visitor {
+            user_agent: Some(ua.to_string()),
+            accept_language: Some(lang.to_string()),
+            country: country.map(|c| c.to_string()),
+            time: Utc
+                .with_ymd_and_hms(2024, 12, 24, hour, 0, 0)
+                .single()
+                .expect("time should be valid"),
+        }
+    }
+
+    fn rule(url: &str) -> Rule {
+        Rule {
+            device: None,
+            language: None,
+            country: None,
+            hours: None,
+            url: url.to_string(),
+        }
+    }
+
+    #[test]
+    fn evaluate_should_work() {
+        let rules = vec![
+            Rule {
+                device: Some(vec![Device::Ios]),
+                ..rule("https://apps.apple.com/")
+            },
+            Rule {
+                language: Some(vec!["zh".to_string()]),
+                ..rule("https://www.baidu.com/zh")
+            },
+            Rule {
+                country: Some(vec!["de".to_string()]),
+                ..rule("https://www.baidu.com/de")
+            },
+            Rule {
+                hours: Some(HourRange {
+                    start: 22,
+                    end: 6,
+                    utc_offset: 8,
+                }),
+                ..rule("https://www.baidu.com/night")
+            },
+        ];
+
+        let v = visitor(IPHONE, "zh-CN", None, 12);
+        assert_eq!(evaluate(&rules, &v), Some((0, "https://apps.apple.com/")));
+
+        let v = visitor(MAC, "en;q=0.5,zh-CN", None, 4);
+        assert_eq!(evaluate(&rules, &v), Some((1, "https://www.baidu.com/zh")));
+
+        let v = visitor(MAC, "en-US", Some("DE"), 4);
+        assert_eq!(evaluate(&rules, &v), Some((2, "https://www.baidu.com/de")));
+
+        // 15:00 utc is 23:00 at utc+8
+        let v = visitor(MAC, "en-US", None, 15);
+        assert_eq!(
+            evaluate(&rules, &v),
+            Some((3, "https://www.baidu.com/night"))
+        );
+
+        let v = visitor(MAC, "en-US", None, 4);
+        assert_eq!(evaluate(&rules, &v), None);
+    }
+
+    #[test]
+    fn rule_should_combine_conditions() {
+        let rule = Rule {
+            device: Some(vec![Device::Mobile]),
+            language: Some(vec!["zh-tw".to_string()]),
+            ..rule("https://www.baidu.com/tw")
+        };
+
+        assert!(rule.matches(&visitor(IPHONE, "zh-TW,zh;q=0.9", None, 0)));
+        assert!(!rule.matches(&visitor(IPHONE, "zh-CN", None, 0)));
+        assert!(!rule.matches(&visitor(MAC, "zh-TW", None, 0)));
+
+        assert!(Device::Mac.matches(MAC));
+        assert!(!Device::Mac.matches(IPHONE));
+        assert!(Device::Desktop.matches(MAC));
+    }
+
+    #[test]
+    fn choose_variant_should_work() {
+        let variants = vec![
+            Variant {
+                name: "a".to_string(),
+                url: "https://www.baidu.com/a".to_string(),
+                weight: 3,
+            },
+            Variant {
+                name: "b".to_string(),
+                url: "https://www.baidu.com/b".to_string(),
+                weight: 1,
+            },
+        ];
+        let mut rng = rand::thread_rng();
+
+        let mut a = 0;
+        for _ in 0..4000 {
+            if choose_variant(&variants, None, &mut rng).map(|v| v.name.as_str()) == Some("a") {
+                a += 1;
+            }
+        }
+        // 3000 expected, the bounds are far outside of any realistic deviation
+        assert!((2600..3400).contains(&a), "{}", a);
+
+        let v = choose_variant(&variants, Some("b"), &mut rng);
+        assert_eq!(v.map(|v| v.name.as_str()), Some("b"));
+
+        assert!(choose_variant(&[], None, &mut rng).is_none());
+
+        assert!(Variant::validate(&variants).is_ok());
+
+        let mut bad = variants.clone();
+        bad[1].name = "a".to_string();
+        assert!(Variant::validate(&bad).is_err());
+
+        let mut bad = variants.clone();
+        bad[1].weight = 0;
+        assert!(Variant::validate(&bad).is_err());
+
+        let mut bad = variants;
+        bad[1].name = "b; path=/".to_string();
+        assert!(Variant::validate(&bad).is_err());
+    }
+
+    #[test]
+    fn validate_rule_should_work() {
+        assert!(rule("https://www.baidu.com/").validate().is_err());
+
+        let mut r = Rule {
+            hours: Some(HourRange {
+                start: 9,
+                end: 9,
+                utc_offset: 0,
+            }),
+            ..rule("https://www.baidu.com/")
+        };
+        assert!(r.validate().is_err());
+
+        r.hours = Some(HourRange {
+            start: 9,
+            end: 24,
+            utc_offset: 0,
+        });
+        assert!(r.validate().is_ok());
+
+        r.hours = Some(HourRange {
+            start: 9,
+            end: 17,
+            utc_offset: i32::MIN,
+        });
+        assert!(r.validate().is_err());
+    }
+
+    #[test]
+    fn app_links_should_work() {
+        const ANDROID: &str = "Mozilla/5.0 (Linux; Android 14; Pixel 8) Mobile Safari/537.36";
+
+        let apps = AppLinks {
+            ios: Some("acme://item/1".to_string()),
+            android: Some(
+                "intent://item/1#Intent;scheme=acme;package=com.acme.app;end".to_string(),
+            ),
+        };
+        assert!(apps.validate().is_ok());
+
+        assert_eq!(
+            apps.choose(&visitor(IPHONE, "en", None, 0)),
+            Some("acme://item/1")
+        );
+        assert_eq!(
+            apps.choose(&visitor(ANDROID, "en", None, 0)),
+            apps.android.as_deref()
+        );
+        assert_eq!(apps.choose(&visitor(MAC, "en", None, 0)), None);
+
+        let apps = AppLinks {
+            ios: Some("javascript:alert(1)".to_string()),
+            android: None,
+        };
+        assert!(apps.validate().is_err());
+
+        let apps = AppLinks {
+            ios: None,
+            android: Some("no scheme".to_string()),
+        };
+        assert!(apps.validate().is_err());
+    }
+}
@@ -1,21 +1,37 @@
 use crate::{AppError, User};
 use jwt_simple::prelude::*;
 
-const JWT_DURATION: u64 = 30 * 24 * 60 * 60;
+pub(crate) const JWT_DURATION: u64 = 30 * 24 * 60 * 60;
 const JWT_ISSUER: &str = "server";
 const JWT_AUDIENCE: &str = "web";
+const SESSION_ID_LEN: usize = 21;
 
 pub struct EncodingKey(Ed25519KeyPair);
 
 pub struct DecodingKey(Ed25519PublicKey);
 
+// only ids go into the token, the user itself is loaded on every request
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TokenClaims {
+    pub uid: i64,
+    // tells apart the logins of the same user, so one of them can be logged out alone
+    pub sid: String,
+    // `token_version` of the user when the token was signed
+    pub ver: i32,
+}
+
 impl EncodingKey {
     pub fn new(pem: &str) -> Result<Self, AppError> {
         Ok(Self(Ed25519KeyPair::from_pem(pem)?))
     }
 
-    pub fn sign(&self, user: User) -> Result<String, AppError> {
-        let claims = Claims::with_custom_claims(user, Duration::from_secs(JWT_DURATION));
+    pub fn sign(&self, user: &User) -> Result<String, AppError> {
+        let custom = TokenClaims {
+            uid: user.id,
+            sid: nanoid::nanoid!(SESSION_ID_LEN),
+            ver: user.token_version,
+        };
+        let claims = Claims::with_custom_claims(custom, Duration::from_secs(JWT_DURATION));
 
         let claims = claims.with_issuer(JWT_ISSUER).with_audience(JWT_AUDIENCE);
 
@@ -28,7 +44,7 @@ impl DecodingKey {
         Ok(Self(Ed25519PublicKey::from_pem(pem)?))
     }
 
-    pub fn verify(&self, token: &str) -> Result<User, AppError> {
+    pub fn verify(&self, token: &str) -> Result<TokenClaims, AppError> {
         let options = VerificationOptions {
             allowed_issuers: Some(HashSet::from_strings(&[JWT_ISSUER])),
             allowed_audiences: Some(HashSet::from_strings(&[JWT_AUDIENCE])),
@@ -45,6 +61,7 @@ impl DecodingKey {
 mod tests {
     use crate::AppState;
     use anyhow::Result;
+    use jwt_simple::prelude::*;
 
     #[tokio::test]
     async fn sign_and_verify_should_work() -> Result<()> {
@@ -57,15 +74,26 @@ mod tests {
             .await?
             .expect("user should exists");
 
-        let token = state.ek.sign(user.clone())?;
+        let token = state.ek.sign(&user)?;
 
         let ret = state.dk.verify(&token)?;
 
-        assert_eq!(user.id, ret.id);
-        assert_eq!(user.name, ret.name);
-        assert_eq!(user.password_hash, ret.password_hash);
-        assert_eq!(user.created_at, ret.created_at);
-        assert_eq!(user.email, ret.email);
+        assert_eq!(ret.uid, user.id);
+        assert_eq!(ret.ver, user.token_version);
+        assert_eq!(ret.sid.len(), 21);
+
+        // every login is its own session
+        let other = state.dk.verify(&state.ek.sign(&user)?)?;
+        assert_ne!(ret.sid, other.sid);
+
+        // nothing but the claims above is in the token
+        let claims = state
+            .dk
+            .0
+            .verify_token::<serde_json::Map<String, serde_json::Value>>(&token, None)?;
+        let mut keys: Vec<_> = claims.custom.keys().map(|k| k.as_str()).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["sid", "uid", "ver"]);
 
         Ok(())
     }